@@ -2,19 +2,28 @@ use bevy::prelude::{AssetEvent, Assets, EventReader, Res, ResMut, Shader};
 
 use pipeline_cache::AppPipelineCache;
 
-mod error;
+pub mod buffer;
+pub mod error;
+#[cfg(test)]
+pub mod harness;
 mod pipeline_cache;
-mod plugin;
-mod traits;
-mod worker;
-mod worker_builder;
+pub mod plugin;
+pub mod runtime;
+pub mod shared;
+pub mod snapshot;
+pub mod texture;
+mod timings;
+pub mod traits;
+pub mod worker;
+pub mod worker_builder;
 
 /// Helper module to import most used elements.
 pub mod prelude {
     pub use super::{
+        buffer::BufferHandle,
         plugin::{AppComputePlugin, AppComputeWorkerPlugin},
         traits::{ComputeShader, ComputeWorker},
-        worker::AppComputeWorker,
+        worker::{AppComputeWorker, ComputeWorkerFinished, ReadbackPolicy},
         worker_builder::AppComputeWorkerBuilder,
     };
 
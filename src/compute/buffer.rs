@@ -0,0 +1,251 @@
+use std::{
+    borrow::Cow,
+    fmt,
+    future::Future,
+    hash::Hash,
+    marker::PhantomData,
+    ops::{Deref, Range},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, Waker},
+};
+
+use bevy::render::render_resource::ShaderType;
+use bytemuck::{cast_slice, AnyBitPattern, Pod, Zeroable};
+use parking_lot::Mutex;
+
+/// Anything that names a resource owned by an [`AppComputeWorker<W>`](super::worker::AppComputeWorker).
+///
+/// It is implemented by [`BufferHandle<T>`] and is what [`add_pass`](super::worker_builder::AppComputeWorkerBuilder::add_pass)
+/// expects, so handles of different types can be bound to the same pass.
+pub trait ResourceHandle {
+    fn name(&self) -> &str;
+}
+
+/// A typed handle to a buffer of an [`AppComputeWorker<W>`](super::worker::AppComputeWorker).
+///
+/// `T` is the type the buffer was created from, e.g. `BufferHandle<Vec<Particle>>`
+/// for a storage buffer filled with `&Vec<Particle>`. Handles are returned by
+/// [`AppComputeWorkerBuilder`](super::worker_builder::AppComputeWorkerBuilder)
+/// but can also be declared as constants with [`BufferHandle::new`].
+pub struct BufferHandle<T> {
+    name: Cow<'static, str>,
+    _phantom: PhantomData<fn() -> T>,
+}
+
+impl<T> BufferHandle<T> {
+    /// Create a handle to the buffer named `name`.
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name: Cow::Borrowed(name),
+            _phantom: PhantomData,
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Self {
+        Self {
+            name: Cow::Owned(name.to_owned()),
+            _phantom: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<T> ResourceHandle for BufferHandle<T> {
+    #[inline]
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<T> Clone for BufferHandle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            name: self.name.clone(),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<T> PartialEq for BufferHandle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl<T> Eq for BufferHandle<T> {}
+
+impl<T> Hash for BufferHandle<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for BufferHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("BufferHandle").field(&self.name).finish()
+    }
+}
+
+/// A typed handle to the push constants of a pass of an [`AppComputeWorker<W>`](super::worker::AppComputeWorker).
+///
+/// Returned by [`add_pass_with_push_constants`](super::worker_builder::AppComputeWorkerBuilder::add_pass_with_push_constants),
+/// to update them with [`set_push_constants`](super::worker::AppComputeWorker::set_push_constants).
+pub struct PushConstantsHandle<T> {
+    pass: usize,
+    _phantom: PhantomData<fn() -> T>,
+}
+
+impl<T> PushConstantsHandle<T> {
+    pub(crate) fn new(pass: usize) -> Self {
+        Self {
+            pass,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Id of the pass in the worker.
+    #[inline]
+    pub(crate) fn pass(&self) -> usize {
+        self.pass
+    }
+}
+
+impl<T> Clone for PushConstantsHandle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for PushConstantsHandle<T> {}
+
+impl<T> fmt::Debug for PushConstantsHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PushConstantsHandle")
+            .field(&self.pass)
+            .finish()
+    }
+}
+
+/// Workgroup counts of an indirect compute pass, as read by `dispatch_workgroups_indirect`.
+///
+/// In WGSL, it matches `struct DispatchIndirectArgs { x: u32, y: u32, z: u32 }`.
+#[derive(ShaderType, Pod, Zeroable, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct DispatchIndirectArgs {
+    pub x: u32,
+    pub y: u32,
+    pub z: u32,
+}
+
+impl DispatchIndirectArgs {
+    pub const fn new(x: u32, y: u32, z: u32) -> Self {
+        Self { x, y, z }
+    }
+}
+
+/// A typed view into a mapped staging buffer.
+///
+/// The data is not copied, it derefs to `&[T]` straight from the mapped memory.
+/// The view must be dropped before the worker unmaps its staging buffers.
+pub struct MappedSlice<'a, T> {
+    view: wgpu::BufferView<'a>,
+    /// The viewed bytes within the mapped buffer.
+    range: Range<usize>,
+    _phantom: PhantomData<&'a [T]>,
+}
+
+impl<'a, T> MappedSlice<'a, T> {
+    pub(crate) fn new(view: wgpu::BufferView<'a>, range: Range<usize>) -> Self {
+        Self {
+            view,
+            range,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<'a, T: AnyBitPattern> Deref for MappedSlice<'a, T> {
+    type Target = [T];
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        cast_slice(&self.view[self.range.clone()])
+    }
+}
+
+struct ReadbackState<T> {
+    data: Option<Vec<T>>,
+    waker: Option<Waker>,
+}
+
+/// The result of [`AppComputeWorker::read_async`](super::worker::AppComputeWorker::read_async).
+///
+/// It is fulfilled with the contents of the staging buffer once the next run of the worker
+/// has been copied back to the CPU. Either `.await` it, or poll it with [`Readback::try_take`].
+pub struct Readback<T> {
+    state: Arc<Mutex<ReadbackState<T>>>,
+}
+
+impl<T: AnyBitPattern + Send> Readback<T> {
+    pub(crate) fn new() -> (Self, PendingReadback) {
+        let state = Arc::new(Mutex::new(ReadbackState {
+            data: None,
+            waker: None,
+        }));
+
+        let sender = state.clone();
+        let pending = PendingReadback(Box::new(move |bytes: &[u8]| {
+            let mut state = sender.lock();
+            state.data = Some(cast_slice::<u8, T>(bytes).to_vec());
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        }));
+
+        (Self { state }, pending)
+    }
+
+    /// Check if the data has been read back.
+    #[inline]
+    pub fn is_ready(&self) -> bool {
+        self.state.lock().data.is_some()
+    }
+
+    /// Take the data if it has been read back, without blocking.
+    #[inline]
+    pub fn try_take(&mut self) -> Option<Vec<T>> {
+        self.state.lock().data.take()
+    }
+}
+
+impl<T> Future for Readback<T> {
+    type Output = Vec<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock();
+        match state.data.take() {
+            Some(data) => Poll::Ready(data),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+type ReadbackFn = dyn FnOnce(&[u8]) + Send + Sync;
+
+/// The worker side of a [`Readback<T>`], called with the mapped bytes of the staging buffer.
+pub(crate) struct PendingReadback(Box<ReadbackFn>);
+
+impl PendingReadback {
+    #[inline]
+    pub(crate) fn fulfill(self, bytes: &[u8]) {
+        (self.0)(bytes)
+    }
+}
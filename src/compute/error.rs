@@ -1,13 +1,56 @@
+use std::{marker::PhantomData, ops::Range, time::Duration};
+
+use bevy::{
+    ecs::{entity::Entity, event::Event},
+    utils::Uuid,
+};
+
+use wgpu::TextureFormat;
+
+use super::{pipeline_cache::ReflectedSpace, traits::ComputeWorker};
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug)]
 pub enum Error {
     BufferNotFound(String),
+    TextureNotFound(String),
+    UnsupportedTextureFormat(String, TextureFormat),
+    ResourceNotFound(String),
     StagingBufferNotFound(String),
+    StagingBufferNotMapped(String),
+    NoFreeStagingSlot,
+    BufferMapFailed(String),
+    BufferOutOfBounds(String, Range<u64>, u64),
+    UnalignedBufferAccess(String, Range<u64>),
+    BufferNotCopyable(String),
+    TooManyElements(String, usize),
+    EmptyResize(String),
+    SharedBufferResize(String),
+    SharedBufferCycle(Vec<String>),
     InvalidStep(String),
+    FlagNotFound(String),
     PipelinesEmpty,
     PipelineNotReady,
+    PipelineFailed(String, String),
+    WorkgroupSizeUnknown(Uuid),
+    ReflectionUnavailable(String),
+    BindingNotFound(String, String),
+    InvalidBinding {
+        pass: usize,
+        shader: String,
+        group: u32,
+        binding: u32,
+        name: String,
+        mismatch: Box<BindingMismatch>,
+    },
     EncoderIsNone,
+    AdapterNotFound,
+    RequestDevice(String),
+    Timeout(Duration),
+    WorkerFailed,
+    InvalidSnapshot(String),
+    SnapshotSizeMismatch(String, u64, u64),
 }
 
 impl std::error::Error for Error {}
@@ -16,13 +59,212 @@ impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Error::BufferNotFound(name) => write!(f, "Buffer {name} not found."),
+            Error::TextureNotFound(name) => write!(f, "Texture {name} not found."),
+            Error::UnsupportedTextureFormat(name, format) => write!(
+                f,
+                "Texture {name} can't be read back, the texels of {format:?} aren't copied as a whole."
+            ),
+            Error::ResourceNotFound(name) => {
+                write!(f, "No buffer, texture or sampler named {name}.")
+            }
             Error::StagingBufferNotFound(name) => write!(f, "Staging buffer {name} not found."),
+            Error::StagingBufferNotMapped(name) => {
+                write!(
+                    f,
+                    "Staging buffer {name} isn't mapped, wait for the worker to be ready."
+                )
+            }
+            Error::NoFreeStagingSlot => write!(
+                f,
+                "Every staging slot is in use, wait for a run to finish before starting another."
+            ),
+            Error::BufferMapFailed(reason) => write!(f, "Failed to map a staging buffer: {reason}"),
+            Error::BufferOutOfBounds(name, range, size) => write!(
+                f,
+                "Bytes {range:?} are out of bounds of buffer {name} ({size} bytes)."
+            ),
+            Error::UnalignedBufferAccess(name, range) => write!(
+                f,
+                "Bytes {range:?} of buffer {name} aren't aligned to {} bytes.",
+                wgpu::COPY_BUFFER_ALIGNMENT
+            ),
+            Error::BufferNotCopyable(name) => write!(
+                f,
+                "Buffer {name} can't be copied from, it wasn't created with `COPY_SRC` usage."
+            ),
+            Error::TooManyElements(name, len) => write!(
+                f,
+                "Buffer {name} can't hold {len} elements, passes dispatch at most {} of them.",
+                u32::MAX
+            ),
+            Error::EmptyResize(name) => {
+                write!(f, "Buffer {name} can't be resized to hold no elements.")
+            }
+            Error::SharedBufferResize(name) => write!(
+                f,
+                "Shared buffer {name} can't be resized, the other workers would keep the old one."
+            ),
+            Error::SharedBufferCycle(workers) => write!(
+                f,
+                "Workers {} can't be ordered, they read the shared buffers each other write.",
+                workers.join(", ")
+            ),
             Error::PipelinesEmpty => {
                 write!(f, "Missing pipelines. Have you added your shader plugins?")
             }
             Error::InvalidStep(step) => write!(f, "Invalid step `{step}`."),
+            Error::FlagNotFound(flag) => write!(f, "Flag {flag} not found."),
             Error::PipelineNotReady => write!(f, "Pipeline isn't ready yet."),
+            Error::PipelineFailed(shader, reason) => {
+                write!(f, "Pipeline of shader {shader} failed to compile: {reason}")
+            }
+            Error::WorkgroupSizeUnknown(uuid) => write!(
+                f,
+                "The workgroup size of shader {uuid} can't be reflected, use `add_pass` instead."
+            ),
+            Error::ReflectionUnavailable(shader) => write!(
+                f,
+                "Shader {shader} can't be reflected to bind buffers by name, bind them by position."
+            ),
+            Error::BindingNotFound(shader, name) => {
+                write!(f, "Shader {shader} doesn't use a binding named `{name}`.")
+            }
+            Error::InvalidBinding {
+                pass,
+                shader,
+                group,
+                binding,
+                name,
+                mismatch,
+            } => write!(
+                f,
+                "Pass {pass} ({shader}), `{name}` at @group({group}) @binding({binding}): {mismatch}."
+            ),
             Error::EncoderIsNone => write!(f, "The command encoder hasn't been initialized."),
+            Error::AdapterNotFound => write!(f, "No adapter matches the requested options."),
+            Error::RequestDevice(reason) => write!(f, "Failed to request a device: {reason}"),
+            Error::Timeout(timeout) => write!(f, "The GPU didn't finish within {timeout:?}."),
+            Error::WorkerFailed => write!(f, "The worker failed, reset it to run it again."),
+            Error::InvalidSnapshot(reason) => write!(f, "Invalid worker snapshot: {reason}"),
+            Error::SnapshotSizeMismatch(name, expected, found) => write!(
+                f,
+                "Buffer {name} is {expected} bytes but its snapshot is {found} bytes."
+            ),
+        }
+    }
+}
+
+/// Why a buffer can't be bound to a shader variable, see [`Error::InvalidBinding`].
+#[derive(Debug)]
+pub enum BindingMismatch {
+    /// The shader uses the variable but no buffer is bound to it.
+    Missing,
+    /// A buffer is bound where the shader doesn't use any variable.
+    Unused,
+    /// The kind of resource bound doesn't match the variable, e.g. a uniform buffer bound to a storage variable.
+    AddressSpace {
+        expected: ReflectedSpace,
+        found: &'static str,
+    },
+    /// A read only storage buffer is bound to a `read_write` variable.
+    ReadOnly,
+    /// The buffer is smaller than the variable's type.
+    TooSmall { size: u64, min_size: u64 },
+}
+
+impl std::fmt::Display for BindingMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BindingMismatch::Missing => write!(f, "nothing is bound to it"),
+            BindingMismatch::Unused => write!(f, "the shader doesn't use this binding"),
+            BindingMismatch::AddressSpace { expected, found } => {
+                write!(f, "expected a resource for a {expected}, found a {found}")
+            }
+            BindingMismatch::ReadOnly => write!(
+                f,
+                "the buffer is read only but bound to a var<storage, read_write>"
+            ),
+            BindingMismatch::TooSmall { size, min_size } => write!(
+                f,
+                "the buffer is {size} bytes but the shader expects at least {min_size} bytes"
+            ),
+        }
+    }
+}
+
+/// Sent when an [`AppComputeWorker<W>`](super::worker::AppComputeWorker) fails.
+///
+/// The worker stops running until [`AppComputeWorker::reset`](super::worker::AppComputeWorker::reset)
+/// is called, instead of panicking.
+#[derive(Event)]
+pub struct ComputeWorkerError<W: ComputeWorker> {
+    pub error: Error,
+    /// Index of the step that failed, in the order steps were added to the builder.
+    pub step: Option<usize>,
+    /// Name of the shader of the pass that failed.
+    pub shader: Option<&'static str>,
+    /// Entity of the worker, if it is a component rather than a resource.
+    pub entity: Option<Entity>,
+    _phantom: PhantomData<W>,
+}
+
+impl<W: ComputeWorker> ComputeWorkerError<W> {
+    #[inline]
+    pub(crate) fn with_step(mut self, step: usize) -> Self {
+        self.step = Some(step);
+        self
+    }
+
+    #[inline]
+    pub(crate) fn with_shader(mut self, shader: &'static str) -> Self {
+        self.shader.get_or_insert(shader);
+        self
+    }
+
+    #[inline]
+    pub(crate) fn with_entity(mut self, entity: Option<Entity>) -> Self {
+        self.entity = entity;
+        self
+    }
+}
+
+impl<W: ComputeWorker> From<Error> for ComputeWorkerError<W> {
+    fn from(error: Error) -> Self {
+        Self {
+            error,
+            step: None,
+            shader: None,
+            entity: None,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<W: ComputeWorker> std::error::Error for ComputeWorkerError<W> {}
+
+impl<W: ComputeWorker> std::fmt::Debug for ComputeWorkerError<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ComputeWorkerError")
+            .field("error", &self.error)
+            .field("step", &self.step)
+            .field("shader", &self.shader)
+            .field("entity", &self.entity)
+            .finish()
+    }
+}
+
+impl<W: ComputeWorker> std::fmt::Display for ComputeWorkerError<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Worker {}", std::any::type_name::<W>())?;
+        if let Some(entity) = self.entity {
+            write!(f, " of {entity:?}")?;
+        }
+        if let Some(step) = self.step {
+            write!(f, ", step {step}")?;
+        }
+        if let Some(shader) = self.shader {
+            write!(f, " ({shader})")?;
         }
+        write!(f, ": {}", self.error)
     }
 }
@@ -0,0 +1,142 @@
+use std::{marker::PhantomData, time::Duration};
+
+use bevy::{
+    asset::{AssetId, Handle},
+    ecs::world::Mut,
+    prelude::{App, Assets, Resource, Shader},
+    reflect::TypeUuid,
+    render::{render_resource::ShaderRef, renderer::RenderDevice},
+};
+use bytemuck::AnyBitPattern;
+
+use super::{
+    buffer::BufferHandle, error::ComputeWorkerError, runtime::ComputeRuntime,
+    traits::ComputeWorker, worker::AppComputeWorker,
+};
+
+/// How long a test waits for a run before failing.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The [`ComputeShader::shader`](super::traits::ComputeShader::shader) of a test shader `S`,
+/// whose source is added with [`load_wgsl`].
+pub fn shader_handle<S: TypeUuid>() -> ShaderRef {
+    ShaderRef::Handle(Handle::Weak(shader_id::<S>()))
+}
+
+/// Add the WGSL `source` of the test shader `S` to `app`, see [`shader_handle`].
+pub fn load_wgsl<S: TypeUuid>(app: &mut App, source: &'static str) {
+    app.world.resource_mut::<Assets<Shader>>().insert(
+        shader_id::<S>(),
+        Shader::from_wgsl(source, std::any::type_name::<S>()),
+    );
+}
+
+fn shader_id<S: TypeUuid>() -> AssetId<Shader> {
+    AssetId::Uuid { uuid: S::TYPE_UUID }
+}
+
+/// Runs a [`ComputeWorker`] headlessly in tests, on a [`ComputeRuntime`].
+///
+/// ```ignore
+/// let mut harness = ComputeHarness::<MyWorker>::new(|app| MyShader::load_shader(app));
+/// harness.insert_resource(Parameters::default()).run(1);
+///
+/// let buffers = harness.resource::<MyBuffers>();
+/// assert_eq!(harness.read_vec(&buffers.result), [1, 2, 3]);
+/// ```
+pub struct ComputeHarness<W: ComputeWorker> {
+    runtime: ComputeRuntime,
+    built: bool,
+    _phantom: PhantomData<W>,
+}
+
+impl<W: ComputeWorker> ComputeHarness<W> {
+    /// Create a harness with the shaders `load_shaders` adds to the `Assets<Shader>` of an app,
+    /// e.g. with `load_internal_asset!`.
+    ///
+    /// Panics if no adapter is available, not even a software one.
+    pub fn new(load_shaders: impl FnOnce(&mut App)) -> Self {
+        let mut runtime = match ComputeRuntime::new() {
+            Ok(runtime) => runtime,
+            Err(err) => panic!("Can't run compute tests: {err}"),
+        };
+        runtime.set_timeout(DEFAULT_TIMEOUT);
+
+        let mut app = App::new();
+        app.init_resource::<Assets<Shader>>();
+        load_shaders(&mut app);
+        for (id, shader) in app.world.resource::<Assets<Shader>>().iter() {
+            runtime.add_shader(id, shader.clone());
+        }
+
+        Self {
+            runtime,
+            built: false,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Fail runs that take longer than `timeout`, 10 seconds by default.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.runtime.set_timeout(timeout);
+        self
+    }
+
+    /// Insert a resource the worker reads while being built.
+    pub fn insert_resource<R: Resource>(&mut self, resource: R) -> &mut Self {
+        self.runtime.insert_resource(resource);
+        self
+    }
+
+    /// Get a resource, e.g. the buffer handles the worker inserted while being built.
+    #[inline]
+    pub fn resource<R: Resource>(&self) -> &R {
+        self.runtime.resource::<R>()
+    }
+
+    #[inline]
+    pub fn render_device(&self) -> &RenderDevice {
+        self.runtime.render_device()
+    }
+
+    /// Build the worker if it isn't yet.
+    pub fn build(&mut self) -> &mut Self {
+        if !self.built {
+            self.runtime.add_worker::<W>();
+            self.built = true;
+        }
+        self
+    }
+
+    /// Build the worker if it isn't yet, and run it `iterations` times.
+    pub fn try_run(&mut self, iterations: u32) -> Result<&mut Self, ComputeWorkerError<W>> {
+        self.build().runtime.try_step::<W>(iterations)?;
+        Ok(self)
+    }
+
+    /// Build the worker if it isn't yet, and run it `iterations` times.
+    ///
+    /// Panics with the reason if a pipeline fails to compile or a run times out.
+    pub fn run(&mut self, iterations: u32) -> &mut Self {
+        if let Err(err) = self.try_run(iterations) {
+            panic!("{}", err);
+        }
+        self
+    }
+
+    #[inline]
+    pub fn worker(&self) -> &AppComputeWorker<W> {
+        self.runtime.worker::<W>()
+    }
+
+    /// Get the worker, e.g. to write to its buffers between runs.
+    #[inline]
+    pub fn worker_mut(&mut self) -> Mut<'_, AppComputeWorker<W>> {
+        self.runtime.worker_mut::<W>()
+    }
+
+    /// Copy the elements of `target` read back by the last run.
+    pub fn read_vec<B: AnyBitPattern>(&self, target: &BufferHandle<Vec<B>>) -> Vec<B> {
+        self.worker().read_slice(target).to_vec()
+    }
+}
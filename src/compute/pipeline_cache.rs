@@ -7,6 +7,7 @@
 use std::borrow::Cow;
 use std::iter::FusedIterator;
 use std::mem;
+use std::sync::Arc;
 
 use bevy::prelude::*;
 use bevy::render::render_resource::{
@@ -21,12 +22,54 @@ use parking_lot::Mutex;
 #[cfg(feature = "shader_format_spirv")]
 use wgpu::util::make_spirv;
 use wgpu::{
-    Features, PipelineLayout, PipelineLayoutDescriptor, PushConstantRange, ShaderModuleDescriptor,
+    BindGroupLayoutDescriptor, BindGroupLayoutEntry, BindingType, BufferBindingType, Features,
+    PipelineLayout, PipelineLayoutDescriptor, PushConstantRange, SamplerBindingType,
+    ShaderModuleDescriptor, ShaderStages, StorageTextureAccess, TextureFormat, TextureSampleType,
+    TextureViewDimension,
 };
 
 pub struct CachedAppPipeline {
     state: CachedPipelineState,
     descriptor: Box<ComputePipelineDescriptor>,
+    /// The composed module the pipeline was created from, used for reflection.
+    /// `None` until the pipeline is created, or if the shader wasn't WGSL.
+    module: Option<Arc<naga::Module>>,
+    /// Number of times the pipeline was compiled or failed to, e.g. after its shader changed.
+    generation: u64,
+}
+
+/// A resource bound by the entry point of a compute pipeline, as declared in the shader.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReflectedBinding {
+    /// Name of the global variable.
+    pub name: String,
+    pub group: u32,
+    pub binding: u32,
+    pub space: ReflectedSpace,
+    /// Size of the variable's type, with a single element for runtime-sized arrays.
+    pub min_size: u64,
+}
+
+/// The address space of a [`ReflectedBinding`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReflectedSpace {
+    Uniform,
+    Storage {
+        read_only: bool,
+    },
+    /// Textures and samplers.
+    Handle,
+}
+
+impl std::fmt::Display for ReflectedSpace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReflectedSpace::Uniform => write!(f, "var<uniform>"),
+            ReflectedSpace::Storage { read_only: true } => write!(f, "var<storage, read>"),
+            ReflectedSpace::Storage { read_only: false } => write!(f, "var<storage, read_write>"),
+            ReflectedSpace::Handle => write!(f, "texture or sampler"),
+        }
+    }
 }
 
 /// Index of a cached compute pipeline in a [`PipelineCache`].
@@ -43,10 +86,17 @@ impl CachedAppComputePipelineId {
     }
 }
 
+/// A shader module processed with a set of shader defs.
+#[derive(Clone)]
+struct ProcessedShader {
+    module: ErasedShaderModule,
+    naga: Option<Arc<naga::Module>>,
+}
+
 #[derive(Default)]
 struct ShaderData {
     pipelines: HashSet<CachedAppComputePipelineId>,
-    processed_shaders: HashMap<Vec<ShaderDefVal>, ErasedShaderModule>,
+    processed_shaders: HashMap<Vec<ShaderDefVal>, ProcessedShader>,
     resolved_imports: HashMap<ShaderImport, AssetId<Shader>>,
     dependents: HashSet<AssetId<Shader>>,
 }
@@ -139,7 +189,7 @@ impl ShaderCache {
         pipeline: CachedAppComputePipelineId,
         shader_asset_id: &AssetId<Shader>,
         shader_defs: &[ShaderDefVal],
-    ) -> Result<ErasedShaderModule, PipelineCacheError> {
+    ) -> Result<ProcessedShader, PipelineCacheError> {
         let shader = self
             .shaders
             .get(shader_asset_id)
@@ -180,9 +230,9 @@ impl ShaderCache {
                     "processing shader {:?}, with shader defs {:?}",
                     shader_asset_id, shader_defs
                 );
-                let shader_source = match &shader.source {
+                let (shader_source, naga) = match &shader.source {
                     #[cfg(feature = "shader_format_spirv")]
-                    Source::SpirV(data) => make_spirv(data),
+                    Source::SpirV(data) => (make_spirv(data), None),
                     #[cfg(not(feature = "shader_format_spirv"))]
                     Source::SpirV(_) => {
                         unimplemented!(
@@ -214,14 +264,16 @@ impl ShaderCache {
                             })
                             .collect::<std::collections::HashMap<_, _>>();
 
-                        let naga = self.composer.make_naga_module(
+                        let module = self.composer.make_naga_module(
                             naga_oil::compose::NagaModuleDescriptor {
                                 shader_defs,
                                 ..shader.into()
                             },
                         )?;
 
-                        wgpu::ShaderSource::Naga(Cow::Owned(naga))
+                        // Keep a copy around for reflection
+                        let naga = Arc::new(module.clone());
+                        (wgpu::ShaderSource::Naga(Cow::Owned(module)), Some(naga))
                     }
                 };
 
@@ -246,7 +298,10 @@ impl ShaderCache {
                     return Err(PipelineCacheError::CreateShaderModule(description));
                 }
 
-                entry.insert(ErasedShaderModule::new(shader_module))
+                entry.insert(ProcessedShader {
+                    module: ErasedShaderModule::new(shader_module),
+                    naga,
+                })
             }
         };
 
@@ -380,6 +435,8 @@ impl AppPipelineCache {
         new_pipelines.push(CachedAppPipeline {
             descriptor: Box::new(descriptor),
             state: CachedPipelineState::Queued,
+            module: None,
+            generation: 0,
         });
         id
     }
@@ -403,7 +460,11 @@ impl AppPipelineCache {
                 continue;
             }
 
-            pipeline.state = self.process_compute_pipeline(id, &pipeline.descriptor);
+            (pipeline.state, pipeline.module) =
+                match self.process_compute_pipeline(id, &pipeline.descriptor) {
+                    Ok((pipeline, module)) => (CachedPipelineState::Ok(pipeline), module),
+                    Err(err) => (CachedPipelineState::Err(err), None),
+                };
 
             if let CachedPipelineState::Err(err) = &pipeline.state {
                 match err {
@@ -411,19 +472,19 @@ impl AppPipelineCache {
                     | PipelineCacheError::ShaderImportNotYetAvailable => {
                         // retry
                         self.waiting_pipelines.insert(id);
+                        continue;
                     }
                     // shader could not be processed ... retrying won't help
                     PipelineCacheError::ProcessShaderError(err) => {
                         let error_detail = err.emit_to_string(&self.shader_cache.composer);
                         error!("failed to process shader:\n{}", error_detail);
-                        continue;
                     }
                     PipelineCacheError::CreateShaderModule(description) => {
                         error!("failed to create shader module: {}", description);
-                        continue;
                     }
                 }
             }
+            pipeline.generation += 1;
         }
 
         self.pipelines = pipelines;
@@ -433,9 +494,50 @@ impl AppPipelineCache {
         &mut self,
         id: CachedAppComputePipelineId,
         descriptor: &ComputePipelineDescriptor,
-    ) -> CachedPipelineState {
+    ) -> Result<(Pipeline, Option<Arc<naga::Module>>), PipelineCacheError> {
+        let processed_shader = self.shader_cache.get(
+            &self.device,
+            id,
+            &descriptor.shader.id(),
+            &descriptor.shader_defs,
+        )?;
+
+        // wgpu panics on a missing entry point instead of failing the pipeline
+        if let Some(module) = processed_shader.naga.as_deref() {
+            let found = module.entry_points.iter().any(|entry_point| {
+                entry_point.name == descriptor.entry_point
+                    && entry_point.stage == naga::ShaderStage::Compute
+            });
+            if !found {
+                return Err(PipelineCacheError::CreateShaderModule(format!(
+                    "the shader has no compute entry point named `{}`",
+                    descriptor.entry_point
+                )));
+            }
+        }
+
         let layout = if descriptor.layout.is_empty() && descriptor.push_constant_ranges.is_empty() {
             None
+        } else if descriptor.layout.is_empty() {
+            // wgpu only derives layouts without push constants, derive the bind groups like it does
+            let bind_group_layouts = processed_shader
+                .naga
+                .as_deref()
+                .and_then(|module| {
+                    reflect_bind_group_layouts(&self.device, module, &descriptor.entry_point)
+                })
+                .ok_or_else(|| {
+                    PipelineCacheError::CreateShaderModule(
+                        "the bind group layouts of a pipeline with push constants can't be \
+                         reflected, set `ComputeShader::layouts`"
+                            .to_owned(),
+                    )
+                })?;
+            Some(self.layout_cache.get(
+                &self.device,
+                &bind_group_layouts,
+                descriptor.push_constant_ranges.to_vec(),
+            ))
         } else {
             Some(self.layout_cache.get(
                 &self.device,
@@ -444,28 +546,16 @@ impl AppPipelineCache {
             ))
         };
 
-        let compute_module = match self.shader_cache.get(
-            &self.device,
-            id,
-            &descriptor.shader.id(),
-            &descriptor.shader_defs,
-        ) {
-            Ok(module) => module,
-            Err(err) => {
-                return CachedPipelineState::Err(err);
-            }
-        };
-
         let descriptor = wgpu::ComputePipelineDescriptor {
             label: descriptor.label.as_deref(),
             layout,
-            module: &compute_module,
+            module: &processed_shader.module,
             entry_point: descriptor.entry_point.as_ref(),
         };
 
         let pipeline = self.device.create_compute_pipeline(&descriptor);
 
-        CachedPipelineState::Ok(Pipeline::ComputePipeline(pipeline))
+        Ok((Pipeline::ComputePipeline(pipeline), processed_shader.naga))
     }
 
     #[inline]
@@ -483,6 +573,84 @@ impl AppPipelineCache {
         }
     }
 
+    /// Get why a pipeline failed to compile, if it did.
+    pub fn get_compute_pipeline_error(&self, id: CachedAppComputePipelineId) -> Option<String> {
+        let CachedPipelineState::Err(err) = &self.pipelines.get(id.0)?.state else {
+            return None;
+        };
+
+        Some(match err {
+            PipelineCacheError::ProcessShaderError(err) => {
+                err.emit_to_string(&self.shader_cache.composer)
+            }
+            err => err.to_string(),
+        })
+    }
+
+    /// Get how many times a pipeline was compiled or failed to, so that its users
+    /// can tell when it was recompiled, e.g. after its shader was hot-reloaded.
+    #[inline]
+    pub fn get_compute_pipeline_generation(&self, id: CachedAppComputePipelineId) -> u64 {
+        self.pipelines
+            .get(id.0)
+            .map_or(0, |pipeline| pipeline.generation)
+    }
+
+    /// Check if a pipeline will be compiled again, e.g. once the shaders it imports are loaded.
+    #[inline]
+    pub fn is_compute_pipeline_waiting(&self, id: CachedAppComputePipelineId) -> bool {
+        self.waiting_pipelines.contains(&id)
+    }
+
+    /// Get the composed [`naga::Module`] of a ready pipeline, to reflect on it.
+    #[inline]
+    pub fn get_compute_pipeline_module(
+        &self,
+        id: CachedAppComputePipelineId,
+    ) -> Option<&naga::Module> {
+        self.pipelines.get(id.0)?.module.as_deref()
+    }
+
+    /// Get the `@workgroup_size` of the entry point of a ready pipeline.
+    #[inline]
+    pub fn get_workgroup_size(&self, id: CachedAppComputePipelineId) -> Option<[u32; 3]> {
+        let name = &self.pipelines.get(id.0)?.descriptor.entry_point;
+        self.get_compute_pipeline_module(id)?
+            .entry_points
+            .iter()
+            .find(|entry_point| entry_point.name == *name)
+            .map(|entry_point| entry_point.workgroup_size)
+    }
+
+    /// Get the resources bound by the entry point of a ready pipeline,
+    /// ignoring the global variables it doesn't use.
+    pub fn get_bindings(&self, id: CachedAppComputePipelineId) -> Option<Vec<ReflectedBinding>> {
+        let pipeline = self.pipelines.get(id.0)?;
+        let module = pipeline.module.as_ref()?;
+
+        let bindings = used_bindings(module, &pipeline.descriptor.entry_point)?
+            .into_iter()
+            .map(|(binding, global)| {
+                let space = match global.space {
+                    naga::AddressSpace::Uniform => ReflectedSpace::Uniform,
+                    naga::AddressSpace::Storage { access } => ReflectedSpace::Storage {
+                        read_only: !access.contains(naga::StorageAccess::STORE),
+                    },
+                    _ => ReflectedSpace::Handle,
+                };
+                ReflectedBinding {
+                    name: global.name.clone().unwrap_or_default(),
+                    group: binding.group,
+                    binding: binding.binding,
+                    space,
+                    min_size: module.types[global.ty].inner.size(module.to_ctx()) as u64,
+                }
+            })
+            .collect();
+
+        Some(bindings)
+    }
+
     pub fn set_shader(&mut self, shader_asset_id: &AssetId<Shader>, shader: &Shader) {
         let pipelines_to_queue = self
             .shader_cache
@@ -502,6 +670,211 @@ impl AppPipelineCache {
     }
 }
 
+/// The global variables bound to a resource and used by `entry_point` of `module`.
+fn used_bindings<'a>(
+    module: &'a naga::Module,
+    entry_point: &str,
+) -> Option<Vec<(&'a naga::ResourceBinding, &'a naga::GlobalVariable)>> {
+    let entry_point = module
+        .entry_points
+        .iter()
+        .position(|candidate| candidate.name == entry_point)?;
+
+    // The module has already been validated when its shader was processed
+    let info =
+        naga::valid::Validator::new(naga::valid::ValidationFlags::empty(), Capabilities::all())
+            .validate(module)
+            .ok()?;
+    let uses = info.get_entry_point(entry_point);
+
+    let bindings = module
+        .global_variables
+        .iter()
+        .filter(|(handle, _)| !uses[*handle].is_empty())
+        .filter_map(|(_, global)| Some((global.binding.as_ref()?, global)))
+        .collect();
+    Some(bindings)
+}
+
+/// Create the layouts of the bind groups used by `entry_point` of `module`,
+/// as wgpu derives them for a pipeline without layout.
+fn reflect_bind_group_layouts(
+    device: &RenderDevice,
+    module: &naga::Module,
+    entry_point: &str,
+) -> Option<Vec<BindGroupLayout>> {
+    let sampled = sampled_images(module);
+    let mut groups: Vec<Vec<BindGroupLayoutEntry>> = vec![];
+    for (binding, global) in used_bindings(module, entry_point)? {
+        let buffer = |ty| BindingType::Buffer {
+            ty,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        };
+        let ty = match (global.space, &module.types[global.ty].inner) {
+            (naga::AddressSpace::Uniform, _) => buffer(BufferBindingType::Uniform),
+            (naga::AddressSpace::Storage { access }, _) => buffer(BufferBindingType::Storage {
+                read_only: !access.contains(naga::StorageAccess::STORE),
+            }),
+            (_, naga::TypeInner::Sampler { comparison: true }) => {
+                BindingType::Sampler(SamplerBindingType::Comparison)
+            }
+            (_, naga::TypeInner::Sampler { comparison: false }) => {
+                BindingType::Sampler(SamplerBindingType::Filtering)
+            }
+            (
+                _,
+                naga::TypeInner::Image {
+                    dim,
+                    arrayed,
+                    class,
+                },
+            ) => image_binding_type(*dim, *arrayed, *class, sampled.contains(binding)),
+            // Binding arrays
+            _ => return None,
+        };
+
+        let group = binding.group as usize;
+        if groups.len() <= group {
+            groups.resize(group + 1, vec![]);
+        }
+        groups[group].push(BindGroupLayoutEntry {
+            binding: binding.binding,
+            visibility: ShaderStages::COMPUTE,
+            ty,
+            count: None,
+        });
+    }
+
+    let layouts = groups
+        .iter()
+        .map(|entries| {
+            device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: None,
+                entries,
+            })
+        })
+        .collect();
+    Some(layouts)
+}
+
+/// The images of `module` sampled with a sampler, the others are only loaded from.
+fn sampled_images(module: &naga::Module) -> HashSet<naga::ResourceBinding> {
+    let functions = module.functions.iter().map(|(_, function)| function).chain(
+        module
+            .entry_points
+            .iter()
+            .map(|entry_point| &entry_point.function),
+    );
+
+    let mut sampled = HashSet::default();
+    for function in functions {
+        for (_, expression) in function.expressions.iter() {
+            let naga::Expression::ImageSample { image, .. } = expression else {
+                continue;
+            };
+            if let naga::Expression::GlobalVariable(global) = function.expressions[*image] {
+                sampled.extend(module.global_variables[global].binding.clone());
+            }
+        }
+    }
+    sampled
+}
+
+/// The binding type of an image, whose float texels are filterable only if it is `sampled`,
+/// so that textures of unfilterable formats can be loaded from.
+fn image_binding_type(
+    dim: naga::ImageDimension,
+    arrayed: bool,
+    class: naga::ImageClass,
+    sampled: bool,
+) -> BindingType {
+    let view_dimension = match dim {
+        naga::ImageDimension::D1 => TextureViewDimension::D1,
+        naga::ImageDimension::D2 if arrayed => TextureViewDimension::D2Array,
+        naga::ImageDimension::D2 => TextureViewDimension::D2,
+        naga::ImageDimension::D3 => TextureViewDimension::D3,
+        naga::ImageDimension::Cube if arrayed => TextureViewDimension::CubeArray,
+        naga::ImageDimension::Cube => TextureViewDimension::Cube,
+    };
+
+    match class {
+        naga::ImageClass::Sampled { kind, multi } => BindingType::Texture {
+            sample_type: match kind {
+                naga::ScalarKind::Sint => TextureSampleType::Sint,
+                naga::ScalarKind::Uint => TextureSampleType::Uint,
+                naga::ScalarKind::Float | naga::ScalarKind::Bool => TextureSampleType::Float {
+                    filterable: sampled,
+                },
+            },
+            view_dimension,
+            multisampled: multi,
+        },
+        naga::ImageClass::Depth { multi } => BindingType::Texture {
+            sample_type: TextureSampleType::Depth,
+            view_dimension,
+            multisampled: multi,
+        },
+        naga::ImageClass::Storage { format, access } => BindingType::StorageTexture {
+            access: if !access.contains(naga::StorageAccess::LOAD) {
+                StorageTextureAccess::WriteOnly
+            } else if access.contains(naga::StorageAccess::STORE) {
+                StorageTextureAccess::ReadWrite
+            } else {
+                StorageTextureAccess::ReadOnly
+            },
+            format: storage_format(format),
+            view_dimension,
+        },
+    }
+}
+
+fn storage_format(format: naga::StorageFormat) -> TextureFormat {
+    use naga::StorageFormat as Sf;
+    use TextureFormat as Tf;
+
+    match format {
+        Sf::R8Unorm => Tf::R8Unorm,
+        Sf::R8Snorm => Tf::R8Snorm,
+        Sf::R8Uint => Tf::R8Uint,
+        Sf::R8Sint => Tf::R8Sint,
+        Sf::R16Uint => Tf::R16Uint,
+        Sf::R16Sint => Tf::R16Sint,
+        Sf::R16Float => Tf::R16Float,
+        Sf::Rg8Unorm => Tf::Rg8Unorm,
+        Sf::Rg8Snorm => Tf::Rg8Snorm,
+        Sf::Rg8Uint => Tf::Rg8Uint,
+        Sf::Rg8Sint => Tf::Rg8Sint,
+        Sf::R32Uint => Tf::R32Uint,
+        Sf::R32Sint => Tf::R32Sint,
+        Sf::R32Float => Tf::R32Float,
+        Sf::Rg16Uint => Tf::Rg16Uint,
+        Sf::Rg16Sint => Tf::Rg16Sint,
+        Sf::Rg16Float => Tf::Rg16Float,
+        Sf::Rgba8Unorm => Tf::Rgba8Unorm,
+        Sf::Rgba8Snorm => Tf::Rgba8Snorm,
+        Sf::Rgba8Uint => Tf::Rgba8Uint,
+        Sf::Rgba8Sint => Tf::Rgba8Sint,
+        Sf::Rgb10a2Unorm => Tf::Rgb10a2Unorm,
+        Sf::Rg11b10Float => Tf::Rg11b10Float,
+        Sf::Rg32Uint => Tf::Rg32Uint,
+        Sf::Rg32Sint => Tf::Rg32Sint,
+        Sf::Rg32Float => Tf::Rg32Float,
+        Sf::Rgba16Uint => Tf::Rgba16Uint,
+        Sf::Rgba16Sint => Tf::Rgba16Sint,
+        Sf::Rgba16Float => Tf::Rgba16Float,
+        Sf::Rgba32Uint => Tf::Rgba32Uint,
+        Sf::Rgba32Sint => Tf::Rgba32Sint,
+        Sf::Rgba32Float => Tf::Rgba32Float,
+        Sf::R16Unorm => Tf::R16Unorm,
+        Sf::R16Snorm => Tf::R16Snorm,
+        Sf::Rg16Unorm => Tf::Rg16Unorm,
+        Sf::Rg16Snorm => Tf::Rg16Snorm,
+        Sf::Rgba16Unorm => Tf::Rgba16Unorm,
+        Sf::Rgba16Snorm => Tf::Rgba16Snorm,
+    }
+}
+
 struct ErrorSources<'a> {
     current: Option<&'a (dyn std::error::Error + 'static)>,
 }
@@ -526,3 +899,61 @@ impl<'a> Iterator for ErrorSources<'a> {
 }
 
 impl<'a> FusedIterator for ErrorSources<'a> {}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{prelude::World, reflect::TypeUuid, render::render_resource::ShaderRef};
+
+    use crate::compute::{
+        error::{ComputeWorkerError, Error},
+        harness::{load_wgsl, shader_handle, ComputeHarness},
+        traits::{ComputeShader, ComputeWorker},
+        worker::AppComputeWorker,
+        worker_builder::AppComputeWorkerBuilder,
+    };
+
+    const SHADER: &str = r"
+        @group(0) @binding(0) var<storage, read_write> cells: array<u32, 4>;
+
+        @compute @workgroup_size(2)
+        fn count(@builtin(global_invocation_id) id: vec3<u32>) {
+            cells[id.x] += 1u;
+        }
+    ";
+
+    #[derive(TypeUuid)]
+    #[uuid = "61c8f2d4-5a3e-4b97-8d10-c4e7b2a9f358"]
+    struct CellsShader;
+
+    impl ComputeShader for CellsShader {
+        fn shader() -> ShaderRef {
+            shader_handle::<Self>()
+        }
+    }
+
+    struct TypoWorker;
+
+    impl ComputeWorker for TypoWorker {
+        fn build(world: &mut World) -> AppComputeWorker<Self> {
+            let mut builder = AppComputeWorkerBuilder::new(world);
+            let cells = builder.add_staging("cells", &vec![1u32, 2, 3, 4]);
+            builder.add_pass_entry::<CellsShader>("cuont", [2, 1, 1], &[&cells]);
+            builder.one_shot().build()
+        }
+    }
+
+    #[test]
+    fn test_missing_entry_point() {
+        let mut harness =
+            ComputeHarness::<TypoWorker>::new(|app| load_wgsl::<CellsShader>(app, SHADER));
+        assert!(matches!(
+            harness.try_run(1),
+            Err(ComputeWorkerError {
+                error: Error::PipelineFailed(..),
+                shader: Some(shader),
+                entity: None,
+                ..
+            }) if shader.ends_with("CellsShader")
+        ));
+    }
+}
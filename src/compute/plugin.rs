@@ -1,17 +1,30 @@
 use std::marker::PhantomData;
 
-use bevy::{prelude::*, render::renderer::RenderDevice};
+use bevy::{
+    diagnostic::RegisterDiagnostic,
+    ecs::system::{SystemId, SystemState},
+    prelude::*,
+    render::renderer::RenderDevice,
+    utils::HashMap,
+};
 
 use super::{
-    extract_shaders, pipeline_cache::AppPipelineCache, process_pipeline_queue_system,
-    traits::ComputeWorker, worker::AppComputeWorker,
+    error::ComputeWorkerError,
+    extract_shaders,
+    pipeline_cache::AppPipelineCache,
+    process_pipeline_queue_system,
+    shared::SharedComputeBuffers,
+    traits::{ComputeWorker, ComputeWorkerComponent},
+    worker::{AppComputeWorker, ComputeShaderDefs, ComputeWorkerFinished},
 };
 
 /// The main plugin. Always include it if you want to use `bevy_app_compute`
 pub struct AppComputePlugin;
 
 impl Plugin for AppComputePlugin {
-    fn build(&self, _app: &mut App) {}
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SharedComputeBuffers>();
+    }
 
     fn finish(&self, app: &mut App) {
         let render_device = app.world.resource::<RenderDevice>().clone();
@@ -20,6 +33,21 @@ impl Plugin for AppComputePlugin {
             .add_systems(PreUpdate, extract_shaders)
             .add_systems(Update, process_pipeline_queue_system);
     }
+
+    fn cleanup(&self, app: &mut App) {
+        // Workers writing to shared buffers submit before those reading from them,
+        // once every worker plugin has built its worker
+        let dependencies = match app.world.resource::<SharedComputeBuffers>().ordering() {
+            Ok(dependencies) => dependencies,
+            Err(err) => panic!("{err}"),
+        };
+        for (writer, reader) in dependencies {
+            app.configure_sets(
+                PostUpdate,
+                ComputeWorkerSet(reader).after(ComputeWorkerSet(writer)),
+            );
+        }
+    }
 }
 
 /// Plugin to initialise your [`AppComputeWorker<W>`] structs.
@@ -41,11 +69,373 @@ impl<W: ComputeWorker> Plugin for AppComputeWorkerPlugin<W> {
     fn finish(&self, app: &mut App) {
         let worker = W::build(&mut app.world);
 
+        let diagnostics = worker.timing_diagnostics();
+        if !diagnostics.is_empty() {
+            for diagnostic in diagnostics {
+                app.register_diagnostic(diagnostic);
+            }
+            app.add_systems(Last, AppComputeWorker::<W>::publish_timings);
+        }
+
+        if app.world.contains_resource::<Assets<Image>>() {
+            app.add_systems(Update, AppComputeWorker::<W>::extract_images);
+        }
+
         app.insert_resource(worker)
-            .add_systems(Update, AppComputeWorker::<W>::extract_pipelines)
+            .add_event::<ComputeWorkerError<W>>()
+            .add_event::<ComputeWorkerFinished<W>>()
+            .init_resource::<ComputeShaderDefs<W>>()
+            .add_systems(
+                Update,
+                (
+                    AppComputeWorker::<W>::apply_shader_defs
+                        .run_if(resource_changed::<ComputeShaderDefs<W>>()),
+                    AppComputeWorker::<W>::extract_pipelines,
+                )
+                    .chain(),
+            )
             .add_systems(
                 PostUpdate,
-                (AppComputeWorker::<W>::unmap_all, AppComputeWorker::<W>::run).chain(),
+                (AppComputeWorker::<W>::unmap_all, AppComputeWorker::<W>::run)
+                    .chain()
+                    .in_set(ComputeWorkerSet(std::any::type_name::<W>())),
             );
     }
 }
+
+/// Systems running the [`AppComputeWorker<W>`] resource, labeled by the type name of `W`.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+struct ComputeWorkerSet(&'static str);
+
+/// Plugin to run the [`AppComputeWorker<W>`] components of entities,
+/// built from their [`ComputeWorkerComponent::Config`].
+///
+/// [`ComputeShaderDefs<W>`] specialize every worker of `W`. Component workers can't use
+/// [`SharedComputeBuffers`] nor [`timings`](super::worker_builder::AppComputeWorkerBuilder::timings):
+/// they are built once the schedule is, too late to be ordered after the workers writing to
+/// the buffers, and all of them would share the same diagnostics. Building one that does panics.
+pub struct AppComputeWorkerComponentPlugin<W: ComputeWorkerComponent> {
+    _phantom: PhantomData<W>,
+}
+
+impl<W: ComputeWorkerComponent> Default for AppComputeWorkerComponentPlugin<W> {
+    fn default() -> Self {
+        Self {
+            _phantom: Default::default(),
+        }
+    }
+}
+
+impl<W: ComputeWorkerComponent> Plugin for AppComputeWorkerComponentPlugin<W> {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ComputeWorkerError<W>>()
+            .add_event::<ComputeWorkerFinished<W>>()
+            .init_resource::<ComputeShaderDefs<W>>()
+            .init_resource::<ComponentWorkerSystems<W>>()
+            .add_systems(
+                Update,
+                (
+                    remove_worker_components::<W>,
+                    build_worker_components::<W>,
+                    AppComputeWorker::<W>::apply_component_shader_defs
+                        .run_if(resource_changed::<ComputeShaderDefs<W>>()),
+                    AppComputeWorker::<W>::extract_component_pipelines,
+                )
+                    .chain(),
+            )
+            .add_systems(
+                PostUpdate,
+                (
+                    AppComputeWorker::<W>::unmap_components,
+                    AppComputeWorker::<W>::run_components,
+                )
+                    .chain(),
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        if app.world.contains_resource::<Assets<Image>>() {
+            app.add_systems(Update, AppComputeWorker::<W>::extract_component_images);
+        }
+    }
+}
+
+/// The systems registered with
+/// [`on_finished_system`](super::worker_builder::AppComputeWorkerBuilder::on_finished_system)
+/// by the component workers of each entity, removed along with their worker.
+#[derive(Resource)]
+struct ComponentWorkerSystems<W: ComputeWorkerComponent> {
+    systems: HashMap<Entity, Vec<SystemId>>,
+    _phantom: PhantomData<W>,
+}
+
+impl<W: ComputeWorkerComponent> Default for ComponentWorkerSystems<W> {
+    fn default() -> Self {
+        Self {
+            systems: HashMap::default(),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<W: ComputeWorkerComponent> ComponentWorkerSystems<W> {
+    /// Replace the systems of `entity` with `systems`, removing the previous ones from `world`.
+    fn replace(world: &mut World, entity: Entity, systems: Option<Vec<SystemId>>) {
+        let mut registered = world.resource_mut::<Self>();
+        let previous = match systems {
+            Some(systems) => registered.systems.insert(entity, systems),
+            None => registered.systems.remove(&entity),
+        };
+        for system in previous.into_iter().flatten() {
+            // Only fails if it was already removed
+            let _ = world.remove_system(system);
+        }
+    }
+}
+
+/// Build the workers of the entities whose config was added or changed.
+fn build_worker_components<W: ComputeWorkerComponent>(
+    world: &mut World,
+    configs: &mut QueryState<(Entity, &W::Config), Changed<W::Config>>,
+) {
+    let configs: Vec<_> = configs
+        .iter(world)
+        .map(|(entity, config)| (entity, config.clone()))
+        .collect();
+
+    for (entity, config) in configs {
+        let mut worker = W::build_from_config(&config, world);
+        assert!(
+            !worker.uses_shared_buffers(),
+            "Component workers of {} can't use shared buffers",
+            std::any::type_name::<W>()
+        );
+        assert!(
+            !worker.has_timings(),
+            "Component workers of {} can't publish timings",
+            std::any::type_name::<W>()
+        );
+        worker.set_all_shader_defs(world.resource::<ComputeShaderDefs<W>>());
+
+        let systems = worker.on_finished_systems.clone();
+        world.entity_mut(entity).insert(worker);
+        ComponentWorkerSystems::<W>::replace(world, entity, Some(systems));
+    }
+}
+
+/// Remove the workers of the entities whose config was removed, or that were despawned.
+fn remove_worker_components<W: ComputeWorkerComponent>(
+    world: &mut World,
+    removed: &mut SystemState<RemovedComponents<W::Config>>,
+) {
+    let entities: Vec<_> = removed.get_mut(world).read().collect();
+    for entity in entities {
+        if let Some(mut entity) = world.get_entity_mut(entity) {
+            entity.remove::<AppComputeWorker<W>>();
+        }
+        ComponentWorkerSystems::<W>::replace(world, entity, None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::{
+        ecs::event::ManualEventReader, reflect::TypeUuid, render::render_resource::ShaderRef,
+    };
+
+    use super::*;
+    use crate::compute::{
+        harness::shader_handle, runtime::ComputeRuntime, traits::ComputeShader,
+        worker_builder::AppComputeWorkerBuilder,
+    };
+
+    #[derive(TypeUuid)]
+    #[uuid = "5d8e1f3a-6b27-4c90-a4d2-0e9b7c3f1a58"]
+    struct OffsetShader;
+
+    impl ComputeShader for OffsetShader {
+        fn shader() -> ShaderRef {
+            shader_handle::<Self>()
+        }
+    }
+
+    #[derive(Component, Clone)]
+    struct Offset(u32);
+
+    struct OffsetWorker;
+
+    impl ComputeWorker for OffsetWorker {
+        fn build(world: &mut World) -> AppComputeWorker<Self> {
+            Self::build_from_config(&Offset(0), world)
+        }
+    }
+
+    impl ComputeWorkerComponent for OffsetWorker {
+        type Config = Offset;
+
+        fn build_from_config(config: &Offset, world: &mut World) -> AppComputeWorker<Self> {
+            let mut builder = AppComputeWorkerBuilder::new(world);
+            let values = builder.add_staging("values", &vec![config.0; 4]);
+            builder
+                .add_pass::<OffsetShader>([1, 1, 1], &[&values])
+                .on_finished_system(|| {});
+            builder.build()
+        }
+    }
+
+    struct SharedWorker;
+
+    impl ComputeWorker for SharedWorker {
+        fn build(world: &mut World) -> AppComputeWorker<Self> {
+            Self::build_from_config(&Offset(0), world)
+        }
+    }
+
+    impl ComputeWorkerComponent for SharedWorker {
+        type Config = Offset;
+
+        fn build_from_config(_: &Offset, world: &mut World) -> AppComputeWorker<Self> {
+            let mut builder = AppComputeWorkerBuilder::new(world);
+            builder.read_shared::<Vec<u32>>("shared");
+            builder.build()
+        }
+    }
+
+    #[derive(Resource, Default)]
+    struct Runs(u64);
+
+    /// Reads back its values while the next run is in flight.
+    struct CountWorker;
+
+    impl ComputeWorker for CountWorker {
+        fn build(world: &mut World) -> AppComputeWorker<Self> {
+            Self::build_from_config(&Offset(0), world)
+        }
+    }
+
+    impl ComputeWorkerComponent for CountWorker {
+        type Config = Offset;
+
+        fn build_from_config(config: &Offset, world: &mut World) -> AppComputeWorker<Self> {
+            let mut builder = AppComputeWorkerBuilder::new(world);
+            let values = builder.add_staging("values", &vec![config.0; 4]);
+            let expected = config.0;
+            builder
+                .frames_in_flight(2)
+                .keep_last_results()
+                .on_finished(move |worker, run| {
+                    assert_eq!(worker.last_completed_run(), Some(run));
+                    assert_eq!(worker.read_slice(&values)[..], [expected; 4]);
+                })
+                .on_finished_system(|mut runs: ResMut<Runs>| runs.0 += 1);
+            builder.build()
+        }
+    }
+
+    fn app_with<W: ComputeWorkerComponent>(runtime: &ComputeRuntime) -> App {
+        let render_device = runtime.render_device().clone();
+
+        let mut app = App::new();
+        app.insert_resource(AppPipelineCache::new(render_device.clone()))
+            .insert_resource(render_device)
+            .insert_resource(runtime.render_queue().clone())
+            .init_resource::<SharedComputeBuffers>()
+            .add_plugins(AppComputeWorkerComponentPlugin::<W>::default());
+        app
+    }
+
+    fn systems_of<W: ComputeWorkerComponent>(app: &App, entity: Entity) -> Vec<SystemId> {
+        app.world.resource::<ComponentWorkerSystems<W>>().systems[&entity].clone()
+    }
+
+    #[test]
+    fn test_component_workers() {
+        let runtime = ComputeRuntime::new().unwrap();
+        let mut app = app_with::<OffsetWorker>(&runtime);
+        app.world
+            .resource_mut::<ComputeShaderDefs<OffsetWorker>>()
+            .set::<OffsetShader>(&["DOUBLE".into()]);
+
+        let entity = app.world.spawn(Offset(1)).id();
+        app.update();
+        let worker = app
+            .world
+            .get::<AppComputeWorker<OffsetWorker>>(entity)
+            .unwrap();
+        assert_eq!(worker.compute_passes()[0].shader_defs, ["DOUBLE".into()]);
+        let systems = systems_of::<OffsetWorker>(&app, entity);
+        assert_eq!(systems.len(), 1);
+
+        // Rebuilding the worker replaces its systems
+        app.world.get_mut::<Offset>(entity).unwrap().0 = 2;
+        app.update();
+        let rebuilt = systems_of::<OffsetWorker>(&app, entity);
+        assert_ne!(rebuilt, systems);
+        assert!(app.world.remove_system(systems[0]).is_err());
+
+        let mut shader_defs = app.world.resource_mut::<ComputeShaderDefs<OffsetWorker>>();
+        shader_defs.set::<OffsetShader>(&[]);
+        assert_eq!(shader_defs.get::<OffsetShader>(), Some(&[][..]));
+        app.update();
+        let worker = app
+            .world
+            .get::<AppComputeWorker<OffsetWorker>>(entity)
+            .unwrap();
+        assert!(worker.compute_passes()[0].shader_defs.is_empty());
+
+        // Removing the config removes the worker and its systems
+        app.world.entity_mut(entity).remove::<Offset>();
+        app.update();
+        assert!(app
+            .world
+            .get::<AppComputeWorker<OffsetWorker>>(entity)
+            .is_none());
+        assert!(app.world.remove_system(rebuilt[0]).is_err());
+        assert!(app
+            .world
+            .resource::<ComponentWorkerSystems<OffsetWorker>>()
+            .systems
+            .is_empty());
+    }
+
+    #[test]
+    fn test_finished_events() {
+        let runtime = ComputeRuntime::new().unwrap();
+        let mut app = app_with::<CountWorker>(&runtime);
+        app.init_resource::<Runs>();
+        let entity = app.world.spawn(Offset(3)).id();
+
+        // Runs complete a few frames after they are submitted
+        let mut reader = ManualEventReader::<ComputeWorkerFinished<CountWorker>>::default();
+        let mut finished = vec![];
+        for _ in 0..100 {
+            app.update();
+            let events = app
+                .world
+                .resource::<Events<ComputeWorkerFinished<CountWorker>>>();
+            finished.extend(reader.read(events).map(|event| (event.run, event.entity)));
+            if finished.len() >= 2 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(finished[..2], [(0, Some(entity)), (1, Some(entity))]);
+        assert_eq!(app.world.resource::<Runs>().0, finished.len() as u64);
+    }
+
+    #[test]
+    #[should_panic(expected = "can't use shared buffers")]
+    fn test_component_worker_shared_buffers() {
+        let runtime = ComputeRuntime::new().unwrap();
+        let mut app = app_with::<SharedWorker>(&runtime);
+        let mut shared = SharedComputeBuffers::default();
+        shared.add_storage("shared", &vec![0u32; 4]);
+        app.insert_resource(shared);
+
+        app.world.spawn(Offset(0));
+        app.update();
+    }
+}
@@ -0,0 +1,350 @@
+use std::{sync::Arc, time::Duration};
+
+use bevy::{
+    asset::AssetId,
+    ecs::world::Mut,
+    prelude::{Resource, Shader, World},
+    render::renderer::{RenderDevice, RenderQueue},
+    tasks::block_on,
+};
+use wgpu::{
+    Backends, Device, DeviceDescriptor, DeviceType, Features, Instance, InstanceDescriptor, Queue,
+    RequestAdapterOptions,
+};
+
+use super::{
+    error::{ComputeWorkerError, Error, Result},
+    pipeline_cache::AppPipelineCache,
+    shared::SharedComputeBuffers,
+    traits::ComputeWorker,
+    worker::AppComputeWorker,
+};
+
+/// Runs [`AppComputeWorker<W>`]s on a device of its own, without a Bevy `App`.
+///
+/// Shaders are composed by the same [`AppPipelineCache`] as in an app,
+/// but they must be added with [`ComputeRuntime::add_shader`] since there is no asset server:
+/// [`ComputeShader::shader`](super::traits::ComputeShader::shader) has to return a handle.
+///
+/// ```ignore
+/// let mut runtime = ComputeRuntime::new()?;
+/// runtime
+///     .add_shader(MyShader::shader_handle(), Shader::from_wgsl(SOURCE, "my_shader.wgsl"))
+///     .add_worker::<MyWorker>();
+///
+/// runtime.run_once::<MyWorker>();
+/// let result = runtime.worker::<MyWorker>().read_slice(&RESULT);
+/// ```
+pub struct ComputeRuntime {
+    world: World,
+    timeout: Option<Duration>,
+}
+
+impl ComputeRuntime {
+    /// Create a runtime on the default adapter, or on a software one if there is none.
+    pub fn new() -> Result<Self> {
+        Self::with_adapter_options(&RequestAdapterOptions::default()).or_else(|_| {
+            Self::with_adapter_options(&RequestAdapterOptions {
+                force_fallback_adapter: true,
+                ..Default::default()
+            })
+        })
+    }
+
+    /// Create a runtime on the adapter matching `options`,
+    /// with every feature and limit it supports.
+    pub fn with_adapter_options(options: &RequestAdapterOptions) -> Result<Self> {
+        let instance = Instance::new(InstanceDescriptor {
+            backends: wgpu::util::backend_bits_from_env().unwrap_or(Backends::all()),
+            ..Default::default()
+        });
+        let Some(adapter) = block_on(instance.request_adapter(options)) else {
+            return Err(Error::AdapterNotFound);
+        };
+
+        // Same as the renderer, mappable primary buffers are slow on discrete GPUs
+        let mut features = adapter.features();
+        if adapter.get_info().device_type == DeviceType::DiscreteGpu {
+            features -= Features::MAPPABLE_PRIMARY_BUFFERS;
+        }
+
+        let (device, queue) = block_on(adapter.request_device(
+            &DeviceDescriptor {
+                label: Some("compute runtime"),
+                features,
+                limits: adapter.limits(),
+            },
+            None,
+        ))
+        .map_err(|err| Error::RequestDevice(err.to_string()))?;
+
+        Ok(Self::from_device(device, queue))
+    }
+
+    /// Create a runtime on an existing device.
+    pub fn from_device(device: Device, queue: Queue) -> Self {
+        let render_device = RenderDevice::from(device);
+
+        let mut world = World::new();
+        world.insert_resource(AppPipelineCache::new(render_device.clone()));
+        world.insert_resource(render_device);
+        world.insert_resource(RenderQueue(Arc::new(queue)));
+        world.init_resource::<SharedComputeBuffers>();
+
+        Self {
+            world,
+            timeout: None,
+        }
+    }
+
+    /// Fail runs that take longer than `timeout` with [`Error::Timeout`], instead of waiting forever.
+    pub fn set_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    #[inline]
+    pub fn render_device(&self) -> &RenderDevice {
+        self.world.resource::<RenderDevice>()
+    }
+
+    #[inline]
+    pub fn render_queue(&self) -> &RenderQueue {
+        self.world.resource::<RenderQueue>()
+    }
+
+    /// Insert a resource that [`ComputeWorker::build`] can read from the world.
+    pub fn insert_resource<R: Resource>(&mut self, resource: R) -> &mut Self {
+        self.world.insert_resource(resource);
+        self
+    }
+
+    /// Get a resource of the world, e.g. the handles a worker inserted while being built.
+    ///
+    /// Panics if it doesn't exist.
+    #[inline]
+    pub fn resource<R: Resource>(&self) -> &R {
+        self.world.resource::<R>()
+    }
+
+    /// Add a shader, either used by a [`ComputeShader`](super::traits::ComputeShader)
+    /// through its handle or imported by another one.
+    pub fn add_shader(&mut self, handle: impl Into<AssetId<Shader>>, shader: Shader) -> &mut Self {
+        self.world
+            .resource_mut::<AppPipelineCache>()
+            .set_shader(&handle.into(), &shader);
+        self
+    }
+
+    /// Build the worker `W`, replacing the previous one if any.
+    pub fn add_worker<W: ComputeWorker>(&mut self) -> &mut Self {
+        if let Some(previous) = self.world.remove_resource::<AppComputeWorker<W>>() {
+            for system in previous.on_finished_systems {
+                // Only fails if it was already removed
+                let _ = self.world.remove_system(system);
+            }
+        }
+        let worker = W::build(&mut self.world);
+        self.world.insert_resource(worker);
+        self
+    }
+
+    /// Get the worker `W`, to read its results.
+    ///
+    /// Panics if it hasn't been added with [`ComputeRuntime::add_worker`].
+    #[inline]
+    pub fn worker<W: ComputeWorker>(&self) -> &AppComputeWorker<W> {
+        self.world.resource::<AppComputeWorker<W>>()
+    }
+
+    /// Get the worker `W`, to write to its buffers.
+    ///
+    /// Panics if it hasn't been added with [`ComputeRuntime::add_worker`].
+    #[inline]
+    pub fn worker_mut<W: ComputeWorker>(&mut self) -> Mut<'_, AppComputeWorker<W>> {
+        self.world.resource_mut::<AppComputeWorker<W>>()
+    }
+
+    /// Compile the pipelines of `W` that aren't yet.
+    fn prepare<W: ComputeWorker>(&mut self) -> std::result::Result<(), ComputeWorkerError<W>> {
+        self.world
+            .resource_scope(|world, mut worker: Mut<AppComputeWorker<W>>| {
+                let mut pipeline_cache = world.resource_mut::<AppPipelineCache>();
+                // Pipelines for new shader defs are compiled along with the others
+                worker.queue_specialized_pipelines(&pipeline_cache);
+                pipeline_cache.process_queue();
+                worker.update_pipelines(&pipeline_cache)?;
+                worker.check_pipelines(&pipeline_cache)
+            })
+    }
+
+    /// Run `W` once and wait for its results, whatever its run mode.
+    /// The systems registered with
+    /// [`AppComputeWorkerBuilder::on_finished_system`](super::worker_builder::AppComputeWorkerBuilder::on_finished_system)
+    /// are run once they can be read.
+    ///
+    /// If it fails, it must be [reset](AppComputeWorker::reset) before running again.
+    pub fn try_run_once<W: ComputeWorker>(
+        &mut self,
+    ) -> std::result::Result<(), ComputeWorkerError<W>> {
+        if self.worker::<W>().failed() {
+            return Err(Error::WorkerFailed.into());
+        }
+        self.prepare::<W>()?;
+
+        let timeout = self.timeout;
+        let mut worker = self.worker_mut::<W>();
+        worker.unmap();
+        worker.try_start_run()?;
+        worker.wait(timeout)?;
+
+        for system in worker.on_finished_systems.clone() {
+            // Only fails if the system is running, which it can't be
+            let _ = self.world.run_system(system);
+        }
+        Ok(())
+    }
+
+    /// Run `W` once and wait for its results, whatever its run mode.
+    /// In case of error, this function will panic.
+    pub fn run_once<W: ComputeWorker>(&mut self) {
+        self.try_run_once::<W>().unwrap()
+    }
+
+    /// Run `W` `n` times in a row, waiting for each run.
+    /// Only the results of the last run can be read.
+    pub fn try_step<W: ComputeWorker>(
+        &mut self,
+        n: u32,
+    ) -> std::result::Result<(), ComputeWorkerError<W>> {
+        for _ in 0..n {
+            self.try_run_once::<W>()?;
+        }
+        Ok(())
+    }
+
+    /// Run `W` `n` times in a row, waiting for each run.
+    /// Only the results of the last run can be read.
+    /// In case of error, this function will panic.
+    pub fn step<W: ComputeWorker>(&mut self, n: u32) {
+        self.try_step::<W>(n).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        reflect::TypeUuid,
+        render::render_resource::{ShaderDefVal, ShaderRef},
+    };
+
+    use super::*;
+    use crate::compute::{
+        buffer::BufferHandle,
+        harness::{load_wgsl, shader_handle, ComputeHarness},
+        traits::ComputeShader,
+        worker_builder::AppComputeWorkerBuilder,
+    };
+
+    const SHADER: &str = r"
+        @group(0) @binding(0) var<storage, read_write> values: array<u32, 4>;
+
+        @compute @workgroup_size(4)
+        fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+        #ifdef TRIPLE
+            values[id.x] *= 3u;
+        #else
+            values[id.x] *= 2u;
+        #endif
+        }
+    ";
+
+    #[derive(TypeUuid)]
+    #[uuid = "0d7c5e3a-9b1f-4a62-8e4d-6c2b7f1a9e35"]
+    struct MultiplyShader;
+
+    impl ComputeShader for MultiplyShader {
+        fn shader() -> ShaderRef {
+            shader_handle::<Self>()
+        }
+    }
+
+    struct MultiplyWorker;
+
+    impl ComputeWorker for MultiplyWorker {
+        fn build(world: &mut World) -> AppComputeWorker<Self> {
+            let mut builder = AppComputeWorkerBuilder::new(world);
+            let values = builder.add_staging("values", &vec![1u32, 2, 3, 4]);
+            builder.add_pass::<MultiplyShader>([1, 1, 1], &[&values]);
+            builder.one_shot().build()
+        }
+    }
+
+    struct TripleWorker;
+
+    impl ComputeWorker for TripleWorker {
+        fn build(world: &mut World) -> AppComputeWorker<Self> {
+            let mut builder = AppComputeWorkerBuilder::new(world);
+            let values = builder.add_staging("values", &vec![1u32, 2, 3, 4]);
+            builder
+                .add_pass_with_defs::<MultiplyShader>(
+                    &[ShaderDefVal::Bool("TRIPLE".to_owned(), true)],
+                    [1, 1, 1],
+                    &[&values],
+                )
+                .add_pass::<MultiplyShader>([1, 1, 1], &[&values]);
+            builder.one_shot().build()
+        }
+    }
+
+    #[test]
+    fn test_pass_with_shader_defs() {
+        let mut harness =
+            ComputeHarness::<TripleWorker>::new(|app| load_wgsl::<MultiplyShader>(app, SHADER));
+        harness.run(1);
+
+        let values = BufferHandle::<Vec<u32>>::from_name("values");
+        assert_eq!(harness.read_vec(&values), [6, 12, 18, 24]);
+    }
+
+    #[test]
+    fn test_run_once_after_set_shader_defs() {
+        let mut harness =
+            ComputeHarness::<MultiplyWorker>::new(|app| load_wgsl::<MultiplyShader>(app, SHADER));
+        harness.run(1);
+
+        harness
+            .worker_mut()
+            .set_shader_defs::<MultiplyShader>(&[ShaderDefVal::Bool("TRIPLE".to_owned(), true)]);
+        harness.run(1);
+
+        let values = BufferHandle::<Vec<u32>>::from_name("values");
+        assert_eq!(harness.read_vec(&values), [6, 12, 18, 24]);
+    }
+
+    #[test]
+    fn test_runtime_without_app() {
+        let mut runtime = ComputeRuntime::new().unwrap();
+        let shader = AssetId::Uuid {
+            uuid: MultiplyShader::TYPE_UUID,
+        };
+        runtime
+            .set_timeout(Duration::from_secs(10))
+            .add_shader(shader, Shader::from_wgsl(SHADER, "multiply"))
+            .add_worker::<MultiplyWorker>();
+
+        let values = BufferHandle::<Vec<u32>>::from_name("values");
+        runtime.run_once::<MultiplyWorker>();
+        assert_eq!(
+            runtime.worker::<MultiplyWorker>().read_slice(&values)[..],
+            [2, 4, 6, 8]
+        );
+
+        // Each run doubles the values written by the previous one
+        runtime.step::<MultiplyWorker>(2);
+        assert_eq!(
+            runtime.worker::<MultiplyWorker>().read_slice(&values)[..],
+            [8, 16, 24, 32]
+        );
+    }
+}
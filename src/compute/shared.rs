@@ -0,0 +1,219 @@
+use bevy::{
+    prelude::Resource,
+    render::{
+        render_resource::{
+            encase::{private::WriteInto, StorageBuffer},
+            Buffer, ShaderType,
+        },
+        renderer::RenderDevice,
+    },
+    utils::{HashMap, HashSet},
+};
+use wgpu::{util::BufferInitDescriptor, BufferDescriptor, BufferUsages};
+
+use super::{
+    buffer::BufferHandle,
+    error::{Error, Result},
+};
+
+/// Storage buffers shared by several [`AppComputeWorker`](super::worker::AppComputeWorker)s,
+/// so that one can read what another wrote without a round-trip to the CPU.
+///
+/// Buffers are declared here once, before the workers are built, then referenced by name with
+/// [`write_shared`](super::worker_builder::AppComputeWorkerBuilder::write_shared) and
+/// [`read_shared`](super::worker_builder::AppComputeWorkerBuilder::read_shared).
+/// The workers writing to a buffer submit their runs before the workers reading from it,
+/// those writing to the same buffer aren't ordered. The app panics on startup if the workers
+/// can't be ordered, e.g. when each one reads a buffer the other writes.
+///
+/// ```ignore
+/// app.add_plugins(AppComputePlugin);
+/// app.world
+///     .resource_mut::<SharedComputeBuffers>()
+///     .add_storage("entries", &entries);
+/// ```
+#[derive(Resource, Default)]
+pub struct SharedComputeBuffers {
+    buffers: HashMap<String, SharedBuffer>,
+}
+
+struct SharedBuffer {
+    /// Contents the buffer is created with, zeroed if empty.
+    contents: Vec<u8>,
+    size: u64,
+    /// Created by the first worker using it.
+    buffer: Option<Buffer>,
+    /// Type names of the workers writing to it.
+    writers: HashSet<&'static str>,
+    /// Type names of the workers only reading from it.
+    readers: HashSet<&'static str>,
+}
+
+impl SharedComputeBuffers {
+    /// Declare a storage buffer filled with `storage`.
+    pub fn add_storage<T: ShaderType + WriteInto>(
+        &mut self,
+        name: &str,
+        storage: &T,
+    ) -> BufferHandle<T> {
+        let mut buffer = StorageBuffer::new(Vec::new());
+        buffer.write::<T>(storage).unwrap();
+
+        let contents = buffer.into_inner();
+        self.declare(name, contents.len() as u64, contents);
+        BufferHandle::from_name(name)
+    }
+
+    /// Declare an empty storage buffer of `size` bytes.
+    pub fn add_empty_storage<T>(&mut self, name: &str, size: u64) -> BufferHandle<T> {
+        self.declare(name, size, vec![]);
+        BufferHandle::from_name(name)
+    }
+
+    fn declare(&mut self, name: &str, size: u64, contents: Vec<u8>) {
+        let previous = self.buffers.insert(
+            name.to_owned(),
+            SharedBuffer {
+                contents,
+                size,
+                buffer: None,
+                writers: HashSet::default(),
+                readers: HashSet::default(),
+            },
+        );
+        assert!(
+            previous.is_none(),
+            "Shared buffer {name} is declared more than once"
+        );
+    }
+
+    /// Get the buffer `name` for `worker`, creating it if it is the first one to use it.
+    pub(crate) fn get(
+        &mut self,
+        render_device: &RenderDevice,
+        name: &str,
+        worker: &'static str,
+        write: bool,
+    ) -> Option<Buffer> {
+        let shared = self.buffers.get_mut(name)?;
+        if write {
+            shared.readers.remove(worker);
+            shared.writers.insert(worker);
+        } else if !shared.writers.contains(worker) {
+            shared.readers.insert(worker);
+        }
+
+        let usage = BufferUsages::COPY_DST | BufferUsages::COPY_SRC | BufferUsages::STORAGE;
+        let buffer = shared.buffer.get_or_insert_with(|| {
+            if shared.contents.is_empty() {
+                render_device.create_buffer(&BufferDescriptor {
+                    label: Some(name),
+                    size: shared.size,
+                    usage,
+                    mapped_at_creation: false,
+                })
+            } else {
+                render_device.create_buffer_with_data(&BufferInitDescriptor {
+                    label: Some(name),
+                    contents: &std::mem::take(&mut shared.contents),
+                    usage,
+                })
+            }
+        });
+        Some(buffer.clone())
+    }
+
+    /// Pairs of workers `(writer, reader)` sharing a buffer, sorted by type name.
+    ///
+    /// Fails with [`Error::SharedBufferCycle`] if the workers can't be ordered,
+    /// e.g. when each one reads a buffer the other writes.
+    pub(crate) fn ordering(&self) -> Result<Vec<(&'static str, &'static str)>> {
+        let mut dependencies: Vec<_> =
+            self.buffers
+                .values()
+                .flat_map(|shared| {
+                    shared.writers.iter().flat_map(|&writer| {
+                        shared.readers.iter().map(move |&reader| (writer, reader))
+                    })
+                })
+                .collect();
+        dependencies.sort_unstable();
+        dependencies.dedup();
+
+        // Kahn's algorithm, the workers left over are part of or after a cycle
+        let mut remaining: HashMap<&str, usize> = HashMap::default();
+        for &(writer, reader) in &dependencies {
+            remaining.entry(writer).or_default();
+            *remaining.entry(reader).or_default() += 1;
+        }
+        let mut ready: Vec<_> = remaining
+            .iter()
+            .filter(|(_, &writers)| writers == 0)
+            .map(|(&worker, _)| worker)
+            .collect();
+        while let Some(worker) = ready.pop() {
+            remaining.remove(worker);
+            for &(writer, reader) in &dependencies {
+                if writer != worker {
+                    continue;
+                }
+                let writers = remaining.get_mut(reader).unwrap();
+                *writers -= 1;
+                if *writers == 0 {
+                    ready.push(reader);
+                }
+            }
+        }
+
+        if !remaining.is_empty() {
+            let mut workers: Vec<_> = remaining.keys().map(|worker| worker.to_string()).collect();
+            workers.sort_unstable();
+            return Err(Error::SharedBufferCycle(workers));
+        }
+        Ok(dependencies)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compute::runtime::ComputeRuntime;
+
+    #[test]
+    fn test_shared_buffers() {
+        let runtime = ComputeRuntime::new().unwrap();
+        let render_device = runtime.render_device();
+
+        let mut shared = SharedComputeBuffers::default();
+        shared.add_storage("positions", &vec![1u32, 2, 3, 4]);
+        shared.add_empty_storage::<Vec<u32>>("cells", 32);
+        assert!(shared.get(render_device, "missing", "a", false).is_none());
+
+        let positions = shared.get(render_device, "positions", "a", true).unwrap();
+        let cells = shared.get(render_device, "cells", "b", true).unwrap();
+        assert_eq!(positions.size(), 16);
+        assert_eq!(cells.size(), 32);
+
+        // Workers share the buffer created by the first one
+        let read = shared.get(render_device, "positions", "b", false).unwrap();
+        assert_eq!(read.id(), positions.id());
+        shared.get(render_device, "cells", "c", false);
+        // Reading a buffer it writes doesn't order a worker after itself
+        shared.get(render_device, "cells", "b", false);
+        assert_eq!(shared.ordering().unwrap(), [("a", "b"), ("b", "c")]);
+
+        shared.get(render_device, "positions", "c", true);
+        assert!(matches!(
+            shared.ordering(),
+            Err(Error::SharedBufferCycle(workers)) if workers == ["b", "c"]
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "declared more than once")]
+    fn test_declared_twice() {
+        let mut shared = SharedComputeBuffers::default();
+        shared.add_empty_storage::<Vec<u32>>("cells", 32);
+        shared.add_empty_storage::<Vec<u32>>("cells", 64);
+    }
+}
@@ -0,0 +1,185 @@
+use std::io::{Read, Write};
+
+use super::error::{Error, Result};
+
+/// Identifies a snapshot file.
+const MAGIC: &[u8; 4] = b"ACWS";
+/// Bumped whenever the layout of snapshots changes.
+const VERSION: u32 = 1;
+
+/// The contents of every buffer of an [`AppComputeWorker<W>`](super::worker::AppComputeWorker),
+/// taken with [`snapshot`](super::worker::AppComputeWorker::snapshot) to be
+/// [restored](super::worker::AppComputeWorker::restore) later, e.g. to replay a run.
+///
+/// Snapshots are saved as little-endian binary:
+/// the magic bytes `ACWS`, the format version as a `u32`, the name of the worker,
+/// the number of buffers as a `u32`, then each buffer's name, size as a `u64` and bytes.
+/// Names are prefixed with their length as a `u32`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WorkerSnapshot {
+    /// Type name of the worker the snapshot was taken from.
+    worker: String,
+    /// Contents of each buffer, sorted by name.
+    buffers: Vec<(String, Vec<u8>)>,
+}
+
+impl WorkerSnapshot {
+    pub(crate) fn new(worker: &str, mut buffers: Vec<(String, Vec<u8>)>) -> Self {
+        buffers.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Self {
+            worker: worker.to_owned(),
+            buffers,
+        }
+    }
+
+    /// Type name of the worker the snapshot was taken from.
+    #[inline]
+    pub fn worker(&self) -> &str {
+        &self.worker
+    }
+
+    /// Names and contents of the buffers, sorted by name.
+    pub fn buffers(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.buffers
+            .iter()
+            .map(|(name, bytes)| (name.as_str(), bytes.as_slice()))
+    }
+
+    /// Get the contents of the buffer `name`.
+    pub fn buffer(&self, name: &str) -> Option<&[u8]> {
+        self.buffers()
+            .find(|(buffer, _)| *buffer == name)
+            .map(|(_, bytes)| bytes)
+    }
+
+    /// Write the snapshot to `writer`, in the format described in [`WorkerSnapshot`].
+    pub fn write_to(&self, mut writer: impl Write) -> std::io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        write_str(&mut writer, &self.worker)?;
+        writer.write_all(&(self.buffers.len() as u32).to_le_bytes())?;
+        for (name, bytes) in &self.buffers {
+            write_str(&mut writer, name)?;
+            writer.write_all(&(bytes.len() as u64).to_le_bytes())?;
+            writer.write_all(bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Read a snapshot written by [`WorkerSnapshot::write_to`].
+    pub fn read_from(mut reader: impl Read) -> Result<Self> {
+        let mut magic = [0; 4];
+        read_exact(&mut reader, &mut magic)?;
+        if &magic != MAGIC {
+            return Err(Error::InvalidSnapshot("not a worker snapshot".to_owned()));
+        }
+
+        let version = read_u32(&mut reader)?;
+        if version != VERSION {
+            return Err(Error::InvalidSnapshot(format!(
+                "version {version} isn't supported, expected {VERSION}"
+            )));
+        }
+
+        let worker = read_str(&mut reader)?;
+        let count = read_u32(&mut reader)?;
+        let mut buffers = Vec::new();
+        for _ in 0..count {
+            let name = read_str(&mut reader)?;
+            let size = read_u64(&mut reader)?;
+
+            // Read through `take` so that a corrupted size can't allocate more than the file holds
+            let mut bytes = Vec::new();
+            (&mut reader)
+                .take(size)
+                .read_to_end(&mut bytes)
+                .map_err(|err| Error::InvalidSnapshot(err.to_string()))?;
+            if bytes.len() as u64 != size {
+                return Err(Error::InvalidSnapshot(format!(
+                    "buffer {name} is truncated"
+                )));
+            }
+            buffers.push((name, bytes));
+        }
+
+        Ok(Self { worker, buffers })
+    }
+
+    /// Encode the snapshot, see [`WorkerSnapshot::write_to`].
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        // Writing to a `Vec` can't fail
+        self.write_to(&mut bytes).unwrap();
+        bytes
+    }
+
+    /// Decode a snapshot encoded by [`WorkerSnapshot::to_bytes`].
+    #[inline]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Self::read_from(bytes)
+    }
+}
+
+fn write_str(writer: &mut impl Write, value: &str) -> std::io::Result<()> {
+    writer.write_all(&(value.len() as u32).to_le_bytes())?;
+    writer.write_all(value.as_bytes())
+}
+
+fn read_exact(reader: &mut impl Read, buf: &mut [u8]) -> Result<()> {
+    reader
+        .read_exact(buf)
+        .map_err(|err| Error::InvalidSnapshot(err.to_string()))
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    let mut bytes = [0; 4];
+    read_exact(reader, &mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64> {
+    let mut bytes = [0; 8];
+    read_exact(reader, &mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_str(reader: &mut impl Read) -> Result<String> {
+    let len = read_u32(reader)? as u64;
+    let mut bytes = Vec::new();
+    reader
+        .take(len)
+        .read_to_end(&mut bytes)
+        .map_err(|err| Error::InvalidSnapshot(err.to_string()))?;
+    if bytes.len() as u64 != len {
+        return Err(Error::InvalidSnapshot("a name is truncated".to_owned()));
+    }
+    String::from_utf8(bytes).map_err(|err| Error::InvalidSnapshot(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let snapshot = WorkerSnapshot::new(
+            "Worker",
+            vec![
+                ("particles".to_owned(), vec![1, 2, 3, 4]),
+                ("params".to_owned(), vec![]),
+            ],
+        );
+        let bytes = snapshot.to_bytes();
+        assert_eq!(&bytes[..4], MAGIC);
+        assert_eq!(WorkerSnapshot::from_bytes(&bytes).unwrap(), snapshot);
+        assert_eq!(snapshot.buffer("particles"), Some(&[1, 2, 3, 4][..]));
+        assert_eq!(snapshot.buffers().next().unwrap().0, "params");
+
+        // Truncated, from another version, or not a snapshot at all
+        assert!(WorkerSnapshot::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        let mut other_version = bytes.clone();
+        other_version[4] += 1;
+        assert!(WorkerSnapshot::from_bytes(&other_version).is_err());
+        assert!(WorkerSnapshot::from_bytes(b"nope").is_err());
+    }
+}
@@ -0,0 +1,332 @@
+use std::borrow::Cow;
+
+use bevy::render::{
+    render_resource::{Buffer, Texture, TextureView},
+    renderer::{RenderDevice, RenderQueue},
+    texture::Image,
+};
+use wgpu::{
+    CommandEncoder, Extent3d, ImageCopyBuffer, ImageDataLayout, TextureUsages,
+    TextureViewDescriptor, COPY_BYTES_PER_ROW_ALIGNMENT,
+};
+
+use super::{
+    buffer::ResourceHandle,
+    error::{Error, Result},
+};
+
+/// A handle to a texture of an [`AppComputeWorker<W>`](super::worker::AppComputeWorker).
+///
+/// Returned by [`add_storage_texture`](super::worker_builder::AppComputeWorkerBuilder::add_storage_texture)
+/// and friends, it can be bound to a pass like any buffer.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TextureHandle {
+    name: Cow<'static, str>,
+}
+
+impl TextureHandle {
+    /// Create a handle to the texture named `name`.
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name: Cow::Borrowed(name),
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Self {
+        Self {
+            name: Cow::Owned(name.to_owned()),
+        }
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl ResourceHandle for TextureHandle {
+    #[inline]
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// A handle to a sampler of an [`AppComputeWorker<W>`](super::worker::AppComputeWorker),
+/// returned by [`add_sampler`](super::worker_builder::AppComputeWorkerBuilder::add_sampler).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SamplerHandle {
+    name: Cow<'static, str>,
+}
+
+impl SamplerHandle {
+    /// Create a handle to the sampler named `name`.
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name: Cow::Borrowed(name),
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Self {
+        Self {
+            name: Cow::Owned(name.to_owned()),
+        }
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl ResourceHandle for SamplerHandle {
+    #[inline]
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// A texture owned by a worker, and the view passes bind.
+#[derive(Clone, Debug)]
+pub(crate) struct WorkerTexture {
+    name: String,
+    pub(crate) texture: Texture,
+    pub(crate) view: TextureView,
+}
+
+impl WorkerTexture {
+    pub(crate) fn new(name: &str, texture: Texture) -> Self {
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        Self {
+            name: name.to_owned(),
+            texture,
+            view,
+        }
+    }
+
+    /// Create a sampled texture filled with the contents of `image`.
+    pub(crate) fn from_image(
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+        name: &str,
+        image: &Image,
+    ) -> Self {
+        let mut descriptor = image.texture_descriptor.clone();
+        descriptor.label = Some(name);
+        descriptor.usage =
+            TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_SRC | TextureUsages::COPY_DST;
+        let texture =
+            render_device.create_texture_with_data(render_queue, &descriptor, &image.data);
+        Self::new(name, texture)
+    }
+
+    /// Bytes per row of texels, as stored in an [`Image`].
+    ///
+    /// Fails for formats whose aspects are copied separately, e.g. depth stencil formats.
+    #[inline]
+    fn bytes_per_row(&self) -> Result<u32> {
+        let format = self.texture.format();
+        let Some(block_size) = format.block_size(None) else {
+            return Err(Error::UnsupportedTextureFormat(self.name.clone(), format));
+        };
+        Ok(self.texture.width() * block_size)
+    }
+
+    /// Bytes per row in a staging buffer, padded as required by `copy_texture_to_buffer`.
+    #[inline]
+    fn padded_bytes_per_row(&self) -> Result<u32> {
+        Ok(self
+            .bytes_per_row()?
+            .next_multiple_of(COPY_BYTES_PER_ROW_ALIGNMENT))
+    }
+
+    /// Size of a staging buffer the whole texture can be copied to.
+    #[inline]
+    pub(crate) fn staging_size(&self) -> Result<u64> {
+        let Extent3d {
+            height,
+            depth_or_array_layers,
+            ..
+        } = self.texture.size();
+        Ok(self.padded_bytes_per_row()? as u64 * height as u64 * depth_or_array_layers as u64)
+    }
+
+    pub(crate) fn copy_to_buffer(
+        &self,
+        encoder: &mut CommandEncoder,
+        buffer: &Buffer,
+    ) -> Result<()> {
+        let size = self.texture.size();
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            ImageCopyBuffer {
+                buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.padded_bytes_per_row()?),
+                    rows_per_image: Some(size.height),
+                },
+            },
+            size,
+        );
+        Ok(())
+    }
+
+    /// Strip the row padding from the bytes of a staging buffer.
+    pub(crate) fn unpad(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        let bytes_per_row = self.bytes_per_row()? as usize;
+        Ok(bytes
+            .chunks_exact(self.padded_bytes_per_row()? as usize)
+            .flat_map(|row| &row[..bytes_per_row])
+            .copied()
+            .collect())
+    }
+
+    /// Build an [`Image`] from the bytes of a staging buffer.
+    pub(crate) fn to_image(&self, bytes: &[u8]) -> Result<Image> {
+        Ok(Image::new(
+            self.texture.size(),
+            self.texture.dimension(),
+            self.unpad(bytes)?,
+            self.texture.format(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        prelude::{AssetId, Assets, Handle, Resource, World},
+        reflect::TypeUuid,
+        render::render_resource::ShaderRef,
+        utils::{HashSet, Uuid},
+    };
+    use wgpu::{SamplerDescriptor, TextureDescriptor, TextureDimension, TextureFormat};
+
+    use super::*;
+    use crate::compute::{
+        harness::{load_wgsl, shader_handle, ComputeHarness},
+        traits::{ComputeShader, ComputeWorker},
+        worker::AppComputeWorker,
+        worker_builder::AppComputeWorkerBuilder,
+    };
+
+    const SHADER: &str = r"
+        @group(0) @binding(0) var image: texture_2d<f32>;
+        @group(0) @binding(1) var image_sampler: sampler;
+        @group(0) @binding(2) var layers: texture_storage_2d_array<rgba8unorm, write>;
+
+        @compute @workgroup_size(2)
+        fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+            let uv = vec2<f32>((f32(id.x) + 0.5) / 2.0, 0.5);
+            let texel = textureSampleLevel(image, image_sampler, uv, 0.0);
+            textureStore(layers, vec2<i32>(i32(id.x), 0), 0, texel);
+            textureStore(layers, vec2<i32>(i32(id.x), 0), 1, texel.abgr);
+        }
+    ";
+
+    #[derive(TypeUuid)]
+    #[uuid = "3f6a9d04-7c2e-4b81-a5d9-0e8b1c4f7a62"]
+    struct LayersShader;
+
+    impl ComputeShader for LayersShader {
+        fn shader() -> ShaderRef {
+            shader_handle::<Self>()
+        }
+    }
+
+    #[derive(Resource)]
+    struct Source(Handle<Image>);
+
+    const LAYERS: TextureHandle = TextureHandle::new("layers");
+    const SAMPLER: SamplerHandle = SamplerHandle::new("sampler");
+
+    /// Copies an image to both layers of a 2D texture array, reversing the channels of the second.
+    struct LayersWorker;
+
+    impl ComputeWorker for LayersWorker {
+        fn build(world: &mut World) -> AppComputeWorker<Self> {
+            let source = world.resource::<Source>().0.clone();
+            let mut builder = AppComputeWorkerBuilder::new(world);
+            let image = builder.add_texture_from_image("image", &source);
+            let sampler = builder.add_sampler(SAMPLER.name(), &SamplerDescriptor::default());
+            let layers = builder.add_staging_texture(
+                LAYERS.name(),
+                Extent3d {
+                    width: 2,
+                    height: 1,
+                    depth_or_array_layers: 2,
+                },
+                TextureDimension::D2,
+                TextureFormat::Rgba8Unorm,
+            );
+            builder
+                .add_pass::<LayersShader>([1, 1, 1], &[&image, &sampler, &layers])
+                .one_shot()
+                .build()
+        }
+    }
+
+    fn image(data: Vec<u8>) -> Image {
+        let size = Extent3d {
+            width: 2,
+            height: 1,
+            depth_or_array_layers: 1,
+        };
+        Image::new(size, TextureDimension::D2, data, TextureFormat::Rgba8Unorm)
+    }
+
+    #[test]
+    fn test_image_to_texture_array() {
+        let source = AssetId::Uuid {
+            uuid: Uuid::from_u128(0x5b1e_27c4_9a3d_4f60_8e12_d7a4_c9f0_3b85),
+        };
+        let mut images = Assets::<Image>::default();
+        images.insert(source, image(vec![1, 2, 3, 4, 5, 6, 7, 8]));
+
+        let mut harness =
+            ComputeHarness::<LayersWorker>::new(|app| load_wgsl::<LayersShader>(app, SHADER));
+        harness
+            .insert_resource(images)
+            .insert_resource(Source(Handle::Weak(source)))
+            .run(1);
+        assert_eq!(
+            harness.worker().read_texture(&LAYERS),
+            [1, 2, 3, 4, 5, 6, 7, 8, 4, 3, 2, 1, 8, 7, 6, 5]
+        );
+
+        // The texture follows the image it's bound to
+        let mut images = Assets::<Image>::default();
+        images.insert(source, image(vec![9, 10, 11, 12, 13, 14, 15, 16]));
+        harness
+            .worker_mut()
+            .update_images(&images, &HashSet::from_iter([source]));
+        harness.run(1);
+        let layers = harness.worker().read_image(&LAYERS);
+        assert_eq!(
+            layers.data,
+            [9, 10, 11, 12, 13, 14, 15, 16, 12, 11, 10, 9, 16, 15, 14, 13]
+        );
+        assert_eq!(layers.texture_descriptor.size.depth_or_array_layers, 2);
+    }
+
+    #[test]
+    fn test_depth_stencil_readback() {
+        let harness = ComputeHarness::<LayersWorker>::new(|_| {});
+        let texture = harness.render_device().create_texture(&TextureDescriptor {
+            label: None,
+            size: Extent3d::default(),
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Depth24PlusStencil8,
+            usage: TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        let texture = WorkerTexture::new("depth", texture);
+        assert!(matches!(
+            texture.staging_size(),
+            Err(Error::UnsupportedTextureFormat(..))
+        ));
+    }
+}
@@ -0,0 +1,282 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    mem::size_of,
+    sync::Arc,
+    time::Instant,
+};
+
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticId},
+    render::{
+        render_resource::Buffer,
+        renderer::{RenderDevice, RenderQueue},
+    },
+    utils::{HashMap, Uuid},
+};
+use parking_lot::Mutex;
+
+use super::worker::{PendingMaps, ReadbackPolicy};
+use wgpu::{
+    BufferDescriptor, BufferUsages, CommandEncoder, Features, QuerySet, QuerySetDescriptor,
+    QueryType,
+};
+
+/// Number of measurements kept by the diagnostics of a worker.
+const MAX_HISTORY_LENGTH: usize = 120;
+
+/// How long the passes and runs of a worker take, to be published as [`Diagnostic`]s.
+///
+/// Passes are timed with timestamp queries when the device supports [`Features::TIMESTAMP_QUERY`].
+/// Whole runs are always timed on the CPU, from their submission to the GPU completing them.
+///
+/// Only the runs read back according to `policy` are timed, or the ones requested.
+pub(crate) struct Timings {
+    policy: ReadbackPolicy,
+    pub(crate) requested: bool,
+    /// Whether the run being recorded is timed.
+    timing: bool,
+    queries: Option<TimestampQueries>,
+    submitted: Vec<Option<Instant>>,
+    completed: Vec<Arc<Mutex<Option<Instant>>>>,
+    /// Milliseconds spent in the passes of each shader during the last completed run.
+    pass_durations: HashMap<Uuid, f64>,
+    /// Milliseconds between the submission and the completion of the last completed run.
+    run_duration: Option<f64>,
+}
+
+struct TimestampQueries {
+    query_set: QuerySet,
+    /// Number of passes that can be timed per run, each one takes two queries.
+    capacity: u32,
+    resolve_buffer: Buffer,
+    /// Where the timestamps of each slot are read back.
+    readback: Vec<Buffer>,
+    /// Whether each readback buffer has been mapped, or is waiting to be.
+    mapped: Vec<bool>,
+    /// Shader of each pass timed in the run being recorded.
+    recording: Vec<Uuid>,
+    /// Shader of each pass timed in the run of each slot.
+    in_flight: Vec<Vec<Uuid>>,
+    /// Nanoseconds per timestamp tick.
+    period: f32,
+}
+
+impl Timings {
+    /// Create timings for runs of at most `passes` dispatches.
+    pub(crate) fn new(
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+        frames_in_flight: usize,
+        passes: u32,
+        policy: ReadbackPolicy,
+    ) -> Self {
+        let queries = (render_device.features().contains(Features::TIMESTAMP_QUERY) && passes > 0)
+            .then(|| {
+                let size = 2 * passes as u64 * size_of::<u64>() as u64;
+                let buffer = |usage| {
+                    render_device.create_buffer(&BufferDescriptor {
+                        label: Some("timestamps"),
+                        size,
+                        usage,
+                        mapped_at_creation: false,
+                    })
+                };
+
+                TimestampQueries {
+                    query_set: render_device
+                        .wgpu_device()
+                        .create_query_set(&QuerySetDescriptor {
+                            label: Some("timestamps"),
+                            ty: QueryType::Timestamp,
+                            count: 2 * passes,
+                        }),
+                    capacity: passes,
+                    resolve_buffer: buffer(BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC),
+                    readback: (0..frames_in_flight)
+                        .map(|_| buffer(BufferUsages::MAP_READ | BufferUsages::COPY_DST))
+                        .collect(),
+                    mapped: vec![false; frames_in_flight],
+                    recording: vec![],
+                    in_flight: vec![vec![]; frames_in_flight],
+                    period: render_queue.get_timestamp_period(),
+                }
+            });
+
+        Self {
+            policy,
+            requested: false,
+            timing: false,
+            queries,
+            submitted: vec![None; frames_in_flight],
+            completed: (0..frames_in_flight).map(|_| default_completed()).collect(),
+            pass_durations: HashMap::default(),
+            run_duration: None,
+        }
+    }
+
+    /// Start recording the `run`-th run, which is timed if the policy reads it back
+    /// or it was requested.
+    #[inline]
+    pub(crate) fn start_run(&mut self, run: u64) {
+        self.timing = std::mem::take(&mut self.requested) || self.policy.reads(run);
+    }
+
+    /// Write the timestamp before a pass of `shader_uuid`,
+    /// return the index of the pass to give to [`Self::end_pass`].
+    #[inline]
+    pub(crate) fn begin_pass(
+        &mut self,
+        encoder: &mut CommandEncoder,
+        shader_uuid: Uuid,
+    ) -> Option<u32> {
+        if !self.timing {
+            return None;
+        }
+        let queries = self.queries.as_mut()?;
+        let index = queries.recording.len() as u32;
+        if index >= queries.capacity {
+            return None;
+        }
+
+        encoder.write_timestamp(&queries.query_set, 2 * index);
+        queries.recording.push(shader_uuid);
+        Some(index)
+    }
+
+    /// Write the timestamp after the pass started with [`Self::begin_pass`].
+    #[inline]
+    pub(crate) fn end_pass(&mut self, encoder: &mut CommandEncoder, index: Option<u32>) {
+        if let (Some(queries), Some(index)) = (&self.queries, index) {
+            encoder.write_timestamp(&queries.query_set, 2 * index + 1);
+        }
+    }
+
+    /// Copy the timestamps of the run being recorded into `slot`.
+    pub(crate) fn resolve(&mut self, encoder: &mut CommandEncoder, slot: usize) {
+        let Some(queries) = &mut self.queries else {
+            return;
+        };
+        if queries.recording.is_empty() {
+            return;
+        }
+
+        let count = 2 * queries.recording.len() as u32;
+        encoder.resolve_query_set(&queries.query_set, 0..count, &queries.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &queries.resolve_buffer,
+            0,
+            &queries.readback[slot],
+            0,
+            count as u64 * size_of::<u64>() as u64,
+        );
+        queries.in_flight[slot] = std::mem::take(&mut queries.recording);
+    }
+
+    /// Start timing the run just submitted in `slot`.
+    pub(crate) fn submitted(&mut self, render_queue: &RenderQueue, slot: usize) {
+        if !std::mem::take(&mut self.timing) {
+            return;
+        }
+        self.submitted[slot] = Some(Instant::now());
+
+        let completed = default_completed();
+        self.completed[slot] = completed.clone();
+        render_queue.on_submitted_work_done(move || {
+            *completed.lock() = Some(Instant::now());
+        });
+    }
+
+    /// Map the timestamps of `slot` along with the staging buffers of the run.
+    pub(crate) fn map(&mut self, slot: usize, pending_maps: &Arc<PendingMaps>) {
+        let Some(queries) = &mut self.queries else {
+            return;
+        };
+        if queries.in_flight[slot].is_empty() {
+            return;
+        }
+
+        pending_maps.map(queries.readback[slot].slice(..));
+        queries.mapped[slot] = true;
+    }
+
+    /// Forget the timestamps of the run of `slot`, which couldn't be mapped.
+    pub(crate) fn discard(&mut self, slot: usize) {
+        self.submitted[slot] = None;
+        if let Some(queries) = &mut self.queries {
+            queries.in_flight[slot].clear();
+            // Only unmap what was mapped, the run may not have been timed
+            if std::mem::take(&mut queries.mapped[slot]) {
+                queries.readback[slot].unmap();
+            }
+        }
+    }
+
+    /// Forget the passes recorded since the last run was submitted, their commands were dropped.
+    pub(crate) fn discard_recording(&mut self) {
+        self.timing = false;
+        if let Some(queries) = &mut self.queries {
+            queries.recording.clear();
+        }
+    }
+
+    /// Read the timings of the run that completed in `slot`.
+    pub(crate) fn collect(&mut self, slot: usize) {
+        let submitted = self.submitted[slot].take();
+        let completed = self.completed[slot].lock().take();
+        if let (Some(submitted), Some(completed)) = (submitted, completed) {
+            let duration = completed.saturating_duration_since(submitted);
+            self.run_duration = Some(duration.as_secs_f64() * 1000.0);
+        }
+
+        let Some(queries) = &mut self.queries else {
+            return;
+        };
+        let shaders = std::mem::take(&mut queries.in_flight[slot]);
+        if shaders.is_empty() {
+            return;
+        }
+
+        self.pass_durations.clear();
+        {
+            let readback = queries.readback[slot].slice(..).get_mapped_range();
+            let timestamps: &[u64] = bytemuck::cast_slice(&readback);
+            for (shader_uuid, pass) in shaders.iter().zip(timestamps.chunks_exact(2)) {
+                let ticks = pass[1].saturating_sub(pass[0]);
+                let duration = ticks as f64 * queries.period as f64 / 1_000_000.0;
+                *self.pass_durations.entry(*shader_uuid).or_default() += duration;
+            }
+        }
+        queries.readback[slot].unmap();
+        queries.mapped[slot] = false;
+    }
+
+    /// Take the durations measured since the last call, per shader and for the whole run.
+    pub(crate) fn take(&mut self) -> (HashMap<Uuid, f64>, Option<f64>) {
+        (
+            std::mem::take(&mut self.pass_durations),
+            self.run_duration.take(),
+        )
+    }
+}
+
+#[inline]
+fn default_completed() -> Arc<Mutex<Option<Instant>>> {
+    Arc::new(Mutex::new(None))
+}
+
+/// Id of the diagnostic of worker `W` for `shader_uuid`, or for whole runs with [`Uuid::nil`].
+pub(crate) fn diagnostic_id<W>(shader_uuid: Uuid) -> DiagnosticId {
+    let mut hasher = DefaultHasher::new();
+    std::any::type_name::<W>().hash(&mut hasher);
+    let worker = hasher.finish() as u128;
+    DiagnosticId(Uuid::from_u128(
+        shader_uuid.as_u128() ^ worker ^ (worker << 64),
+    ))
+}
+
+/// Diagnostic named after the last segment of a type name, e.g. `compute/DensityShader`.
+pub(crate) fn diagnostic(id: DiagnosticId, type_name: &str) -> Diagnostic {
+    let name = type_name.rsplit("::").next().unwrap_or(type_name);
+    Diagnostic::new(id, format!("compute/{name}"), MAX_HISTORY_LENGTH).with_suffix("ms")
+}
@@ -1,5 +1,5 @@
 use bevy::{
-    prelude::World,
+    prelude::{Component, World},
     reflect::TypeUuid,
     render::render_resource::{BindGroupLayout, ShaderDefVal, ShaderRef},
 };
@@ -12,6 +12,18 @@ pub trait ComputeWorker: Sized + Send + Sync + 'static {
     fn build(world: &mut World) -> AppComputeWorker<Self>;
 }
 
+/// Trait to attach [`AppComputeWorker<W>`]s to entities, each one built from the
+/// [`Config`](ComputeWorkerComponent::Config) component of its entity.
+///
+/// Add the [`AppComputeWorkerComponentPlugin<W>`](super::plugin::AppComputeWorkerComponentPlugin),
+/// then spawn entities with a config: their worker is built during the next `Update`,
+/// and rebuilt whenever the config changes. They can't use shared buffers nor timings.
+pub trait ComputeWorkerComponent: ComputeWorker {
+    type Config: Component + Clone;
+
+    fn build_from_config(config: &Self::Config, world: &mut World) -> AppComputeWorker<Self>;
+}
+
 /// Trait to declare your shaders.
 pub trait ComputeShader: TypeUuid + Send + Sync + 'static {
     /// Implement your [`ShaderRef`]
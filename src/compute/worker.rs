@@ -1,21 +1,53 @@
-use core::panic;
-use std::{marker::PhantomData, ops::Deref};
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    marker::PhantomData,
+    mem::size_of,
+    ops::{Deref, Range},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use bevy::{
-    prelude::{Res, ResMut, Resource},
+    diagnostic::{Diagnostic, Diagnostics},
+    ecs::{event::Event, system::SystemId},
+    prelude::{
+        AssetEvent, AssetId, Assets, Commands, Component, Entity, EventReader, EventWriter, Handle,
+        Query, Res, ResMut, Resource,
+    },
     render::{
-        render_resource::{Buffer, ComputePipeline},
+        render_resource::{
+            BindGroup, Buffer, BufferId, BufferSlice, ComputePipeline, ComputePipelineDescriptor,
+            ComputePipelineId, Sampler, SamplerId, ShaderDefVal, TextureViewId,
+        },
         renderer::{RenderDevice, RenderQueue},
+        texture::Image,
     },
-    utils::{HashMap, Uuid},
+    utils::{HashMap, HashSet, Uuid},
 };
 use bytemuck::{bytes_of, cast_slice, from_bytes, AnyBitPattern, NoUninit};
-use wgpu::{BindGroupEntry, CommandEncoder, CommandEncoderDescriptor, ComputePassDescriptor};
+use parking_lot::Mutex;
+use wgpu::{
+    BindGroupEntry, BindingResource, BufferAsyncError, BufferDescriptor, BufferUsages,
+    CommandEncoder, CommandEncoderDescriptor, ComputePassDescriptor, PushConstantRange,
+    ShaderStages, COPY_BUFFER_ALIGNMENT,
+};
 
 use super::{
-    error::{Error, Result},
-    pipeline_cache::{AppPipelineCache, CachedAppComputePipelineId},
-    traits::ComputeWorker,
+    buffer::{
+        BufferHandle, MappedSlice, PendingReadback, PushConstantsHandle, Readback, ResourceHandle,
+    },
+    error::{BindingMismatch, ComputeWorkerError, Error, Result},
+    pipeline_cache::{
+        AppPipelineCache, CachedAppComputePipelineId, ReflectedBinding, ReflectedSpace,
+    },
+    snapshot::WorkerSnapshot,
+    texture::{TextureHandle, WorkerTexture},
+    timings::{self, Timings},
+    traits::{ComputeShader, ComputeWorker},
     worker_builder::AppComputeWorkerBuilder,
 };
 
@@ -25,31 +57,622 @@ pub enum RunMode {
     OneShot(bool),
 }
 
+/// When a staging buffer is copied back to the CPU.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReadbackPolicy {
+    /// Read the buffer back after every run. This is the default.
+    #[default]
+    EveryRun,
+    /// Read the buffer back every `n` runs, starting with the first one.
+    EveryNRuns(u32),
+    /// Read the buffer back after the next run following
+    /// [`AppComputeWorker::request_readback`].
+    OnRequest,
+    /// Never read the buffer back, unless requested with
+    /// [`AppComputeWorker::request_readback`].
+    Never,
+}
+
+impl ReadbackPolicy {
+    /// Check if the `run`-th run is read back, without a request.
+    #[inline]
+    pub(crate) fn reads(&self, run: u64) -> bool {
+        match self {
+            ReadbackPolicy::EveryRun => true,
+            ReadbackPolicy::EveryNRuns(n) => run % u64::from((*n).max(1)) == 0,
+            ReadbackPolicy::OnRequest | ReadbackPolicy::Never => false,
+        }
+    }
+}
+
 #[derive(PartialEq)]
 pub enum WorkerState {
     Created,
     Available,
     Working,
     FinishedWorking,
+    /// A [`ComputeWorkerError<W>`] was sent, the worker won't run until it is reset.
+    Failed,
+}
+
+type WorkerResult<W> = std::result::Result<(), ComputeWorkerError<W>>;
+
+pub(crate) type FinishedFn<W> = dyn Fn(&AppComputeWorker<W>, u64) + Send + Sync;
+
+/// Sent when a run of an [`AppComputeWorker<W>`] completed and its results can be read.
+#[derive(Event)]
+pub struct ComputeWorkerFinished<W: ComputeWorker> {
+    /// Index of the run, counting from 0.
+    pub run: u64,
+    /// Entity of the worker, if it is a component rather than a resource.
+    pub entity: Option<Entity>,
+    _phantom: PhantomData<W>,
+}
+
+impl<W: ComputeWorker> ComputeWorkerFinished<W> {
+    #[inline]
+    pub(crate) fn new(run: u64, entity: Option<Entity>) -> Self {
+        Self {
+            run,
+            entity,
+            _phantom: PhantomData,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
 pub(crate) enum Step {
     ComputePass(ComputePass),
     Swap(String, String),
+    /// Copy the bytes in `range` of `src` to the same range of `dst`, or as much as fits if `None`.
+    Copy {
+        src: String,
+        dst: String,
+        range: Option<Range<u64>>,
+    },
+    /// Fill a buffer with zeros.
+    Clear(String),
+    /// Run `steps` `count` times in a row.
+    Repeat {
+        count: u32,
+        steps: Vec<Step>,
+    },
+    /// Run `steps` only if `flag` is set, see [`AppComputeWorker::set_flag`].
+    If {
+        flag: String,
+        steps: Vec<Step>,
+    },
+}
+
+/// How the workgroup counts of a [`ComputePass`] are decided.
+#[derive(Clone, Debug)]
+pub(crate) enum Dispatch {
+    Direct([u32; 3]),
+    /// Enough workgroups along `x` to cover this many elements, given the shader's `@workgroup_size`.
+    Elements(u32),
+    /// Read from a [`DispatchIndirectArgs`](super::buffer::DispatchIndirectArgs) in `buffer` at `offset`.
+    Indirect {
+        buffer: String,
+        offset: u64,
+    },
+}
+
+/// A buffer, texture or sampler bound to `@binding(binding)` of a bind group.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Binding {
+    pub(crate) binding: u32,
+    pub(crate) resource: String,
 }
 
 #[derive(Clone, Debug)]
 pub(crate) struct ComputePass {
-    pub(crate) workgroups: [u32; 3],
-    pub(crate) vars: Vec<String>,
+    /// Index of the pass in the order they were added, whatever the step it is nested in.
+    pub(crate) id: usize,
+    pub(crate) dispatch: Dispatch,
+    /// Buffers bound to each `@group`.
+    pub(crate) groups: Vec<Vec<Binding>>,
+    /// Buffers to bind to the global variables of the same name,
+    /// `groups` is filled from them once the shader has been reflected.
+    pub(crate) named: Option<Vec<String>>,
+    /// Set at the start of the compute push constant range before dispatching, if not empty.
+    pub(crate) push_constants: Vec<u8>,
+    /// The push constants of the pipeline layout, see [`AppComputeWorkerBuilder::add_pass_with_push_constants`].
+    pub(crate) push_constant_ranges: Vec<PushConstantRange>,
+    pub(crate) shader_uuid: Uuid,
+    pub(crate) shader_name: &'static str,
+    /// Added to [`ComputeShader::shader_defs`], see [`AppComputeWorkerBuilder::add_pass_with_defs`].
+    pub(crate) shader_defs: Vec<ShaderDefVal>,
+    /// [`ComputeShader::entry_point`], unless added with [`AppComputeWorkerBuilder::add_pass_entry`].
+    pub(crate) entry_point: Cow<'static, str>,
+}
+
+/// The pipeline of a [`ComputePass`]: its shader and entry point, specialized with the defs of the pass,
+/// and the push constants of its layout.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct PipelineKey {
     pub(crate) shader_uuid: Uuid,
+    pub(crate) entry_point: Cow<'static, str>,
+    pub(crate) shader_defs: Vec<ShaderDefVal>,
+    pub(crate) push_constant_ranges: Vec<PushConstantRange>,
+}
+
+impl PipelineKey {
+    /// Specialize `descriptor`, the one shared by the pipelines of the shader.
+    pub(crate) fn descriptor(
+        &self,
+        descriptor: &ComputePipelineDescriptor,
+    ) -> ComputePipelineDescriptor {
+        let mut descriptor = descriptor.clone();
+        descriptor.shader_defs.extend_from_slice(&self.shader_defs);
+        descriptor.entry_point = self.entry_point.clone();
+        descriptor.push_constant_ranges = self.push_constant_ranges.clone();
+        descriptor
+    }
+}
+
+impl ComputePass {
+    #[inline]
+    pub(crate) fn pipeline_key(&self) -> PipelineKey {
+        PipelineKey {
+            shader_uuid: self.shader_uuid,
+            entry_point: self.entry_point.clone(),
+            shader_defs: self.shader_defs.clone(),
+            push_constant_ranges: self.push_constant_ranges.clone(),
+        }
+    }
+
+    /// Check if this pass runs the pipeline `key`.
+    #[inline]
+    fn uses(&self, key: &PipelineKey) -> bool {
+        self.shader_uuid == key.shader_uuid
+            && self.entry_point == key.entry_point
+            && self.shader_defs == key.shader_defs
+            && self.push_constant_ranges == key.push_constant_ranges
+    }
+
+    /// Check if `buffer` is bound to this pass.
+    #[inline]
+    fn binds(&self, buffer: &str) -> bool {
+        self.groups.iter().flatten().any(|b| b.resource == buffer)
+            || self.named.iter().flatten().any(|var| var == buffer)
+    }
 }
 
+/// CPU readable copies of a storage buffer.
+///
+/// There is one copy per frame in flight, so a new run can be copied into a
+/// slot while the previous runs are still being mapped.
 #[derive(Clone, Debug)]
 pub(crate) struct StagingBuffer {
-    pub(crate) mapped: bool,
-    pub(crate) buffer: Buffer,
+    pub(crate) slots: Vec<Buffer>,
+    /// Whether each slot has been mapped, or is waiting to be.
+    pub(crate) mapped: Vec<bool>,
+    pub(crate) policy: ReadbackPolicy,
+    pub(crate) requested: bool,
+    /// Size of the buffer read back, the slots still in use when it was resized are reallocated
+    /// once unmapped.
+    size: u64,
+}
+
+impl StagingBuffer {
+    pub(crate) fn new(
+        render_device: &RenderDevice,
+        name: &str,
+        size: u64,
+        frames_in_flight: usize,
+        policy: ReadbackPolicy,
+    ) -> Self {
+        let slots = (0..frames_in_flight)
+            .map(|slot| {
+                render_device.create_buffer(&BufferDescriptor {
+                    label: Some(name),
+                    size,
+                    usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                    // The first slot starts mapped, so the buffer can be read before the first run
+                    mapped_at_creation: slot == 0,
+                })
+            })
+            .collect();
+
+        let mut mapped = vec![false; frames_in_flight];
+        mapped[0] = true;
+
+        Self {
+            slots,
+            mapped,
+            policy,
+            requested: false,
+            size,
+        }
+    }
+
+    /// Reallocate the slots with `size` bytes, the ones still mapped or waiting to be
+    /// once they are unmapped, see [`Self::reallocate_slot`].
+    fn reallocate(&mut self, render_device: &RenderDevice, name: &str, size: u64) {
+        self.size = size;
+        for slot in 0..self.slots.len() {
+            if !self.mapped[slot] {
+                self.reallocate_slot(render_device, name, slot);
+            }
+        }
+    }
+
+    /// Reallocate `slot` if the buffer was resized since the slot was created.
+    fn reallocate_slot(&mut self, render_device: &RenderDevice, name: &str, slot: usize) {
+        if self.slots[slot].size() == self.size {
+            return;
+        }
+        self.slots[slot] = render_device.create_buffer(&BufferDescriptor {
+            label: Some(name),
+            size: self.size,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+    }
+
+    /// Check if the buffer should be read back after the `run`-th run, and consume the request if any.
+    ///
+    /// A requested buffer is read back whatever its policy.
+    #[inline]
+    fn should_read(&mut self, run: u64) -> bool {
+        std::mem::take(&mut self.requested) || self.policy.reads(run)
+    }
+}
+
+/// Return the bytes `range`, if it isn't reversed and fits in a buffer of `size` bytes.
+#[inline]
+fn checked_byte_range(name: &str, range: Range<u64>, size: u64) -> Result<Range<u64>> {
+    if range.start <= range.end && range.end <= size {
+        Ok(range)
+    } else {
+        Err(Error::BufferOutOfBounds(name.to_owned(), range, size))
+    }
+}
+
+/// Size in bytes of `count` elements of `element_size` bytes.
+///
+/// Saturates on overflow, so that [`checked_byte_range`] rejects it
+/// instead of wrapping around to an offset within the buffer.
+#[inline]
+fn element_bytes(count: usize, element_size: u64) -> u64 {
+    (count as u64).saturating_mul(element_size)
+}
+
+/// Make the passes dispatched for the `old_len` elements of `buffer` follow its new length.
+fn resize_dispatches(steps: &mut [Step], buffer: &str, old_len: usize, new_len: u32) {
+    for_each_pass_mut(steps, |compute_pass| {
+        let binds = compute_pass.binds(buffer);
+        if let Dispatch::Elements(count) = &mut compute_pass.dispatch {
+            if *count as usize == old_len && binds {
+                *count = new_len;
+            }
+        }
+    });
+}
+
+/// Number of workgroups of `workgroup_size` needed to cover `count` elements along `x`.
+#[inline]
+fn workgroups_for_elements(count: u32, workgroup_size: [u32; 3]) -> [u32; 3] {
+    [count.div_ceil(workgroup_size[0].max(1)), 1, 1]
+}
+
+/// Call `f` on each compute pass of `steps`, including the ones in nested steps.
+pub(crate) fn try_for_each_pass<'a>(
+    steps: &'a [Step],
+    f: &mut impl FnMut(&'a ComputePass) -> Result<()>,
+) -> Result<()> {
+    for step in steps {
+        match step {
+            Step::ComputePass(compute_pass) => f(compute_pass)?,
+            Step::Repeat { steps, .. } | Step::If { steps, .. } => try_for_each_pass(steps, f)?,
+            Step::Swap(..) | Step::Copy { .. } | Step::Clear(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Call `f` on each compute pass of `steps`, including the ones in nested steps.
+pub(crate) fn try_for_each_pass_mut(
+    steps: &mut [Step],
+    f: &mut impl FnMut(&mut ComputePass) -> Result<()>,
+) -> Result<()> {
+    for step in steps {
+        match step {
+            Step::ComputePass(compute_pass) => f(compute_pass)?,
+            Step::Repeat { steps, .. } | Step::If { steps, .. } => try_for_each_pass_mut(steps, f)?,
+            Step::Swap(..) | Step::Copy { .. } | Step::Clear(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Call `f` on each compute pass of `steps`, including the ones in nested steps.
+pub(crate) fn for_each_pass_mut(steps: &mut [Step], mut f: impl FnMut(&mut ComputePass)) {
+    let _ = try_for_each_pass_mut(steps, &mut |compute_pass| {
+        f(compute_pass);
+        Ok(())
+    });
+}
+
+/// The byte range of the push constants visible from the compute stage.
+pub(crate) fn compute_push_constants(ranges: &[PushConstantRange]) -> Option<Range<u32>> {
+    ranges
+        .iter()
+        .find(|range| range.stages.contains(ShaderStages::COMPUTE))
+        .map(|range| range.range.clone())
+}
+
+/// The images that were loaded or modified since `events` were last read.
+fn changed_images(events: &mut EventReader<AssetEvent<Image>>) -> HashSet<AssetId<Image>> {
+    events
+        .read()
+        .filter_map(|event| match event {
+            AssetEvent::Added { id } | AssetEvent::Modified { id } => Some(*id),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Whether a pass of `steps` waits for its pipeline to compile.
+/// Pipelines missing from `pipelines` aren't pending, dispatching them fails.
+fn pipelines_pending(
+    steps: &[Step],
+    pipelines: &HashMap<PipelineKey, Option<ComputePipeline>>,
+) -> bool {
+    try_for_each_pass(
+        steps,
+        &mut |compute_pass| match pipelines.get(&compute_pass.pipeline_key()) {
+            Some(None) => Err(Error::PipelineNotReady),
+            _ => Ok(()),
+        },
+    )
+    .is_err()
+}
+
+/// Number of passes dispatched by `steps` when every flag is set.
+fn max_dispatches(steps: &[Step]) -> u32 {
+    steps
+        .iter()
+        .map(|step| match step {
+            Step::ComputePass(_) => 1,
+            Step::Repeat { count, steps } => count.saturating_mul(max_dispatches(steps)),
+            Step::If { steps, .. } => max_dispatches(steps),
+            Step::Swap(..) | Step::Copy { .. } | Step::Clear(_) => 0,
+        })
+        .fold(0, u32::saturating_add)
+}
+
+/// Place each of `vars` in the group and binding of the global variable with the same name.
+fn resolve_named_bindings(
+    shader_name: &str,
+    vars: &[String],
+    reflected: &[ReflectedBinding],
+) -> Result<Vec<Vec<Binding>>> {
+    let mut groups: Vec<Vec<Binding>> = vec![];
+    for var in vars {
+        let Some(reflected) = reflected.iter().find(|binding| &binding.name == var) else {
+            return Err(Error::BindingNotFound(
+                shader_name.to_owned(),
+                var.to_owned(),
+            ));
+        };
+
+        let group = reflected.group as usize;
+        if groups.len() <= group {
+            groups.resize(group + 1, vec![]);
+        }
+        groups[group].push(Binding {
+            binding: reflected.binding,
+            resource: var.to_owned(),
+        });
+    }
+    Ok(groups)
+}
+
+/// Identifies the resource bound to a binding, to cache bind groups.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum ResourceId {
+    Buffer(BufferId),
+    TextureView(TextureViewId),
+    Sampler(SamplerId),
+}
+
+/// The pipeline whose layout a bind group follows, its group index
+/// and the resource bound to each of its bindings.
+type BindGroupKey = (ComputePipelineId, u32, Vec<(u32, ResourceId)>);
+
+/// What a pass needs to know about a resource to check it against the shader.
+#[derive(Clone, Copy, Debug)]
+enum BoundResource {
+    Buffer {
+        uniform: bool,
+        read_only: bool,
+        size: u64,
+    },
+    /// A texture or a sampler.
+    Handle,
+}
+
+impl BoundResource {
+    fn description(&self) -> &'static str {
+        match self {
+            BoundResource::Buffer { uniform: true, .. } => "uniform buffer",
+            BoundResource::Buffer { uniform: false, .. } => "storage buffer",
+            BoundResource::Handle => "texture or sampler",
+        }
+    }
+}
+
+/// Check the resources bound to a pass against the bindings its shader uses.
+fn validate_bindings(
+    compute_pass: &ComputePass,
+    reflected: &[ReflectedBinding],
+    resource: impl Fn(&str) -> Option<BoundResource>,
+) -> Result<()> {
+    let invalid = |group, binding, name: &str, mismatch| Error::InvalidBinding {
+        pass: compute_pass.id,
+        shader: compute_pass.shader_name.to_owned(),
+        group,
+        binding,
+        name: name.to_owned(),
+        mismatch: Box::new(mismatch),
+    };
+
+    for (group, bindings) in compute_pass.groups.iter().enumerate() {
+        let group = group as u32;
+        for bound in bindings {
+            let invalid = |mismatch| invalid(group, bound.binding, &bound.resource, mismatch);
+
+            let Some(var) = reflected
+                .iter()
+                .find(|var| var.group == group && var.binding == bound.binding)
+            else {
+                return Err(invalid(BindingMismatch::Unused));
+            };
+            let Some(resource) = resource(&bound.resource) else {
+                return Err(Error::ResourceNotFound(bound.resource.to_owned()));
+            };
+
+            let (read_only, size) = match (var.space, resource) {
+                (ReflectedSpace::Handle, BoundResource::Handle) => continue,
+                (
+                    ReflectedSpace::Uniform,
+                    BoundResource::Buffer {
+                        uniform: true,
+                        read_only,
+                        size,
+                    },
+                )
+                | (
+                    ReflectedSpace::Storage { .. },
+                    BoundResource::Buffer {
+                        uniform: false,
+                        read_only,
+                        size,
+                    },
+                ) => (read_only, size),
+                (expected, resource) => {
+                    return Err(invalid(BindingMismatch::AddressSpace {
+                        expected,
+                        found: resource.description(),
+                    }))
+                }
+            };
+
+            if var.space == (ReflectedSpace::Storage { read_only: false }) && read_only {
+                return Err(invalid(BindingMismatch::ReadOnly));
+            }
+
+            if size < var.min_size {
+                return Err(invalid(BindingMismatch::TooSmall {
+                    size,
+                    min_size: var.min_size,
+                }));
+            }
+        }
+    }
+
+    for var in reflected {
+        let bound = compute_pass
+            .groups
+            .get(var.group as usize)
+            .is_some_and(|bindings| bindings.iter().any(|b| b.binding == var.binding));
+        if !bound {
+            return Err(invalid(
+                var.group,
+                var.binding,
+                &var.name,
+                BindingMismatch::Missing,
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// The buffers of a run that are still being mapped, and why mapping one failed if it did.
+///
+/// The submitted work of the run is counted too, so that a run mapping no buffers
+/// isn't done before the GPU is.
+#[derive(Default)]
+pub(crate) struct PendingMaps {
+    count: AtomicUsize,
+    error: Mutex<Option<BufferAsyncError>>,
+}
+
+impl PendingMaps {
+    /// Count the work submitted to `queue` so far until it is done.
+    pub(crate) fn work_done(self: &Arc<Self>, queue: &RenderQueue) {
+        self.count.fetch_add(1, Ordering::AcqRel);
+
+        let pending_maps = self.clone();
+        queue.on_submitted_work_done(move || {
+            pending_maps.count.fetch_sub(1, Ordering::Release);
+        });
+    }
+
+    /// Map `slice` for reading, counting it until it is.
+    pub(crate) fn map(self: &Arc<Self>, slice: BufferSlice) {
+        self.count.fetch_add(1, Ordering::AcqRel);
+
+        let pending_maps = self.clone();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            if let Err(err) = result {
+                *pending_maps.error.lock() = Some(err);
+            }
+            pending_maps.count.fetch_sub(1, Ordering::Release);
+        });
+    }
+}
+
+/// Shader defs the passes of an [`AppComputeWorker<W>`] are specialized with, per shader.
+///
+/// Changing them recompiles the pipelines of the worker, see [`AppComputeWorker::set_shader_defs`].
+#[derive(Resource)]
+pub struct ComputeShaderDefs<W: ComputeWorker> {
+    shader_defs: HashMap<Uuid, Vec<ShaderDefVal>>,
+    _phantom: PhantomData<W>,
+}
+
+impl<W: ComputeWorker> Default for ComputeShaderDefs<W> {
+    fn default() -> Self {
+        Self {
+            shader_defs: HashMap::default(),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<W: ComputeWorker> ComputeShaderDefs<W> {
+    /// Specialize every pass of `S` with `shader_defs`.
+    pub fn set<S: ComputeShader>(&mut self, shader_defs: &[ShaderDefVal]) -> &mut Self {
+        self.shader_defs.insert(S::TYPE_UUID, shader_defs.to_vec());
+        self
+    }
+
+    #[inline]
+    pub fn get<S: ComputeShader>(&self) -> Option<&[ShaderDefVal]> {
+        self.shader_defs.get(&S::TYPE_UUID).map(Vec::as_slice)
+    }
+}
+
+/// A submitted run whose staging buffers are being mapped.
+struct InFlightRun {
+    run: u64,
+    slot: usize,
+    pending_maps: Arc<PendingMaps>,
+}
+
+impl InFlightRun {
+    #[inline]
+    fn is_mapped(&self) -> bool {
+        self.pending_maps.count.load(Ordering::Acquire) == 0
+    }
+
+    #[inline]
+    fn take_error(&self) -> Option<BufferAsyncError> {
+        self.pending_maps.error.lock().take()
+    }
 }
 
 /// Struct to manage data transfers from/to the GPU
@@ -57,18 +680,50 @@ pub(crate) struct StagingBuffer {
 /// By default, the run mode of the workers is set to continuous,
 /// meaning it will run every frames. If you want to run it deterministically
 /// use the function `one_shot()` in the builder
-#[derive(Resource)]
+///
+/// A worker is usually a resource, but it can also be a component
+/// built for each entity, see [`ComputeWorkerComponent`](super::traits::ComputeWorkerComponent).
+#[derive(Resource, Component)]
 pub struct AppComputeWorker<W: ComputeWorker> {
     pub(crate) state: WorkerState,
     render_device: RenderDevice,
     render_queue: RenderQueue,
-    cached_pipeline_ids: HashMap<Uuid, CachedAppComputePipelineId>,
-    pipelines: HashMap<Uuid, Option<ComputePipeline>>,
+    cached_pipeline_ids: HashMap<PipelineKey, CachedAppComputePipelineId>,
+    /// Descriptor of the pipelines of each shader, without the defs and entry point of their passes.
+    pipeline_descriptors: HashMap<Uuid, ComputePipelineDescriptor>,
+    pipelines: HashMap<PipelineKey, Option<ComputePipeline>>,
+    /// Generation of the cached pipeline each pipeline comes from.
+    pipeline_generations: HashMap<PipelineKey, u64>,
+    workgroup_sizes: HashMap<PipelineKey, [u32; 3]>,
+    /// Whether passes were built or given shader defs that may not have a pipeline yet.
+    respecialized: bool,
     buffers: HashMap<String, Buffer>,
+    /// Element counts of the buffers resized so far, their size being padded.
+    buffer_lens: HashMap<String, usize>,
+    read_only_buffers: HashSet<String>,
+    shared_buffers: HashSet<String>,
+    textures: HashMap<String, WorkerTexture>,
+    /// Textures bound to an [`Image`] asset, see [`AppComputeWorkerBuilder::add_texture_from_image`].
+    images: HashMap<String, Handle<Image>>,
+    samplers: HashMap<String, Sampler>,
+    /// Bind groups shared by the passes of a pipeline binding the same resources.
+    bind_groups: HashMap<BindGroupKey, BindGroup>,
     staging_buffers: HashMap<String, StagingBuffer>,
     steps: Vec<Step>,
+    flags: HashMap<String, bool>,
     command_encoder: Option<CommandEncoder>,
     run_mode: RunMode,
+    frames_in_flight: usize,
+    free_slots: Vec<usize>,
+    in_flight: VecDeque<InFlightRun>,
+    mapped_slot: Option<usize>,
+    pending_readbacks: Vec<(String, PendingReadback)>,
+    runs: u64,
+    last_run: Option<u64>,
+    keep_last_results: bool,
+    on_finished: Vec<Arc<FinishedFn<W>>>,
+    pub(crate) on_finished_systems: Vec<SystemId>,
+    timings: Option<Timings>,
     _phantom: PhantomData<W>,
 }
 
@@ -78,54 +733,88 @@ impl<W: ComputeWorker> From<&AppComputeWorkerBuilder<'_, W>> for AppComputeWorke
         let render_device = builder.world.resource::<RenderDevice>().clone();
         let render_queue = builder.world.resource::<RenderQueue>().clone();
 
-        let pipelines = builder
-            .cached_pipeline_ids
+        let command_encoder =
+            Some(render_device.create_command_encoder(&CommandEncoderDescriptor { label: None }));
+
+        let frames_in_flight = builder.frames_in_flight;
+        let staging_buffers = builder
+            .staging_buffers
             .iter()
-            .map(|(uuid, _)| (*uuid, None))
+            .map(|(name, policy)| {
+                let size = match builder.buffers.get(name) {
+                    Some(buffer) => buffer.size(),
+                    // Checked by `add_staging_texture`
+                    None => builder.textures[name].staging_size().unwrap(),
+                };
+                let staging =
+                    StagingBuffer::new(&render_device, name, size, frames_in_flight, *policy);
+                (name.clone(), staging)
+            })
             .collect();
 
-        let command_encoder =
-            Some(render_device.create_command_encoder(&CommandEncoderDescriptor { label: None }));
+        assert!(
+            !builder.keep_last_results || frames_in_flight > 1,
+            "Keeping the last results requires at least 2 frames in flight"
+        );
+
+        let timings = builder.timings.map(|policy| {
+            let passes = max_dispatches(&builder.steps);
+            Timings::new(
+                &render_device,
+                &render_queue,
+                frames_in_flight,
+                passes,
+                policy,
+            )
+        });
 
-        Self {
+        let mut worker = Self {
             state: WorkerState::Created,
             render_device,
             render_queue,
-            cached_pipeline_ids: builder.cached_pipeline_ids.clone(),
-            pipelines,
+            cached_pipeline_ids: HashMap::default(),
+            pipeline_descriptors: builder.pipeline_descriptors.clone(),
+            pipelines: HashMap::default(),
+            pipeline_generations: HashMap::default(),
+            workgroup_sizes: HashMap::default(),
+            // The passes are final, queue all their pipelines
+            respecialized: true,
             buffers: builder.buffers.clone(),
-            staging_buffers: builder.staging_buffers.clone(),
+            buffer_lens: HashMap::default(),
+            read_only_buffers: builder.read_only_buffers.clone(),
+            shared_buffers: builder.shared_buffers.clone(),
+            textures: builder.textures.clone(),
+            images: builder.images.clone(),
+            samplers: builder.samplers.clone(),
+            bind_groups: HashMap::default(),
+            staging_buffers,
             steps: builder.steps.clone(),
+            flags: builder.flags.clone(),
             command_encoder,
             run_mode: builder.run_mode,
+            frames_in_flight,
+            // Slot 0 is mapped at creation, it will be freed by `unmap_all`
+            free_slots: (1..frames_in_flight).rev().collect(),
+            in_flight: VecDeque::with_capacity(frames_in_flight),
+            mapped_slot: Some(0),
+            pending_readbacks: vec![],
+            runs: 0,
+            last_run: None,
+            keep_last_results: builder.keep_last_results,
+            on_finished: builder.on_finished.clone(),
+            on_finished_systems: builder.on_finished_systems.clone(),
+            timings,
             _phantom: PhantomData,
-        }
+        };
+        worker.queue_specialized_pipelines(builder.world.resource::<AppPipelineCache>());
+        worker
     }
 }
 
 impl<W: ComputeWorker> AppComputeWorker<W> {
     #[inline]
-    fn dispatch(&mut self, index: usize) -> Result<()> {
-        let compute_pass = match &self.steps[index] {
-            Step::ComputePass(compute_pass) => compute_pass,
-            Step::Swap(_, _) => return Err(Error::InvalidStep(format!("{:?}", self.steps[index]))),
-        };
-
-        let mut entries = vec![];
-        for (index, var) in compute_pass.vars.iter().enumerate() {
-            let Some(buffer) = self.buffers.get(var) else {
-                return Err(Error::BufferNotFound(var.to_owned()));
-            };
-
-            let entry = BindGroupEntry {
-                binding: index as u32,
-                resource: buffer.as_entire_binding(),
-            };
-
-            entries.push(entry);
-        }
-
-        let Some(maybe_pipeline) = self.pipelines.get(&compute_pass.shader_uuid) else {
+    fn dispatch(&mut self, compute_pass: &ComputePass) -> Result<()> {
+        let Some(maybe_pipeline) = self.pipelines.get(&compute_pass.pipeline_key()) else {
             return Err(Error::PipelinesEmpty);
         };
 
@@ -133,37 +822,102 @@ impl<W: ComputeWorker> AppComputeWorker<W> {
             return Err(Error::PipelineNotReady);
         };
 
-        let bind_group_layout = pipeline.get_bind_group_layout(0);
-        let bind_group =
-            self.render_device
-                .create_bind_group(None, &bind_group_layout.into(), &entries);
+        let mut keys = Vec::with_capacity(compute_pass.groups.len());
+        for (group, bindings) in compute_pass.groups.iter().enumerate() {
+            let mut resources = Vec::with_capacity(bindings.len());
+            for binding in bindings.iter() {
+                let name = &binding.resource;
+                let resource = if let Some(buffer) = self.buffers.get(name) {
+                    (ResourceId::Buffer(buffer.id()), buffer.as_entire_binding())
+                } else if let Some(texture) = self.textures.get(name) {
+                    let view = &texture.view;
+                    (
+                        ResourceId::TextureView(view.id()),
+                        BindingResource::TextureView(view),
+                    )
+                } else if let Some(sampler) = self.samplers.get(name) {
+                    (
+                        ResourceId::Sampler(sampler.id()),
+                        BindingResource::Sampler(sampler),
+                    )
+                } else {
+                    return Err(Error::ResourceNotFound(name.to_owned()));
+                };
+                resources.push((binding.binding, resource));
+            }
+
+            // Swapped buffers alternate between a few assignments, each one is only created once
+            let key = (
+                pipeline.id(),
+                group as u32,
+                resources
+                    .iter()
+                    .map(|(binding, (id, _))| (*binding, *id))
+                    .collect(),
+            );
+            if !self.bind_groups.contains_key(&key) {
+                let entries = resources
+                    .into_iter()
+                    .map(|(binding, (_, resource))| BindGroupEntry { binding, resource })
+                    .collect::<Vec<_>>();
+
+                let bind_group_layout = pipeline.get_bind_group_layout(group as u32);
+                let bind_group =
+                    self.render_device
+                        .create_bind_group(None, &bind_group_layout.into(), &entries);
+                self.bind_groups.insert(key.clone(), bind_group);
+            }
+            keys.push(key);
+        }
 
         let Some(encoder) = &mut self.command_encoder else {
             return Err(Error::EncoderIsNone);
         };
+        let query = self
+            .timings
+            .as_mut()
+            .and_then(|timings| timings.begin_pass(encoder, compute_pass.shader_uuid));
         {
             let mut cpass = encoder.begin_compute_pass(&ComputePassDescriptor { label: None });
             cpass.set_pipeline(pipeline);
-            cpass.set_bind_group(0, &bind_group, &[]);
-            cpass.dispatch_workgroups(
-                compute_pass.workgroups[0],
-                compute_pass.workgroups[1],
-                compute_pass.workgroups[2],
-            )
+            for (group, key) in keys.iter().enumerate() {
+                cpass.set_bind_group(group as u32, &self.bind_groups[key], &[]);
+            }
+            if let Some(range) = compute_push_constants(&compute_pass.push_constant_ranges) {
+                if !compute_pass.push_constants.is_empty() {
+                    cpass.set_push_constants(range.start, &compute_pass.push_constants);
+                }
+            }
+            match &compute_pass.dispatch {
+                Dispatch::Direct(workgroups) => {
+                    cpass.dispatch_workgroups(workgroups[0], workgroups[1], workgroups[2])
+                }
+                Dispatch::Elements(count) => {
+                    let Some(workgroup_size) =
+                        self.workgroup_sizes.get(&compute_pass.pipeline_key())
+                    else {
+                        return Err(Error::WorkgroupSizeUnknown(compute_pass.shader_uuid));
+                    };
+                    let [x, y, z] = workgroups_for_elements(*count, *workgroup_size);
+                    cpass.dispatch_workgroups(x, y, z)
+                }
+                Dispatch::Indirect { buffer, offset } => {
+                    let Some(indirect_buffer) = self.buffers.get(buffer) else {
+                        return Err(Error::BufferNotFound(buffer.to_owned()));
+                    };
+                    cpass.dispatch_workgroups_indirect(indirect_buffer, *offset)
+                }
+            }
+        }
+        if let Some(timings) = &mut self.timings {
+            timings.end_pass(encoder, query);
         }
 
         Ok(())
     }
 
     #[inline]
-    fn swap(&mut self, index: usize) -> Result<()> {
-        let (buf_a_name, buf_b_name) = match &self.steps[index] {
-            Step::ComputePass(_) => {
-                return Err(Error::InvalidStep(format!("{:?}", self.steps[index])))
-            }
-            Step::Swap(a, b) => (a.as_str(), b.as_str()),
-        };
-
+    fn swap(&mut self, buf_a_name: &str, buf_b_name: &str) -> Result<()> {
         if !self.buffers.contains_key(buf_a_name) {
             return Err(Error::BufferNotFound(buf_a_name.to_owned()));
         }
@@ -175,240 +929,2102 @@ impl<W: ComputeWorker> AppComputeWorker<W> {
         let [buffer_a, buffer_b] = self.buffers.get_many_mut([buf_a_name, buf_b_name]).unwrap();
         std::mem::swap(buffer_a, buffer_b);
 
+        let len_a = self.buffer_lens.remove(buf_a_name);
+        let len_b = self.buffer_lens.remove(buf_b_name);
+        if let Some(len) = len_a {
+            self.buffer_lens.insert(buf_b_name.to_owned(), len);
+        }
+        if let Some(len) = len_b {
+            self.buffer_lens.insert(buf_a_name.to_owned(), len);
+        }
+
         Ok(())
     }
 
     #[inline]
-    fn read_staging_buffers(&mut self) -> Result<&mut Self> {
-        for (name, staging_buffer) in &self.staging_buffers {
-            let Some(encoder) = &mut self.command_encoder else {
-                return Err(Error::EncoderIsNone);
-            };
-            let Some(buffer) = self.buffers.get(name) else {
-                return Err(Error::BufferNotFound(name.to_owned()));
-            };
+    fn copy(&mut self, src_name: &str, dst_name: &str, range: Option<Range<u64>>) -> Result<()> {
+        let Some(src) = self.buffers.get(src_name) else {
+            return Err(Error::BufferNotFound(src_name.to_owned()));
+        };
+        let Some(dst) = self.buffers.get(dst_name) else {
+            return Err(Error::BufferNotFound(dst_name.to_owned()));
+        };
 
-            encoder.copy_buffer_to_buffer(
-                buffer,
-                0,
-                &staging_buffer.buffer,
-                0,
-                staging_buffer.buffer.size(),
-            );
+        if !src.usage().contains(BufferUsages::COPY_SRC) {
+            return Err(Error::BufferNotCopyable(src_name.to_owned()));
         }
-        Ok(self)
-    }
 
-    #[inline]
-    fn map_staging_buffers(&mut self) -> &mut Self {
-        for (_, staging_buffer) in self.staging_buffers.iter_mut() {
-            let read_buffer_slice = staging_buffer.buffer.slice(..);
+        let range = range.unwrap_or(0..src.size().min(dst.size()));
+        checked_byte_range(src_name, range.clone(), src.size())?;
+        let range = checked_byte_range(dst_name, range, dst.size())?;
+        let len = range.end - range.start;
+        if range.start % COPY_BUFFER_ALIGNMENT != 0 || len % COPY_BUFFER_ALIGNMENT != 0 {
+            return Err(Error::UnalignedBufferAccess(src_name.to_owned(), range));
+        }
 
-            read_buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
-                let err = result.err();
-                if err.is_some() {
-                    let some_err = err.unwrap();
-                    panic!("{}", some_err.to_string());
-                }
-            });
+        let Some(encoder) = &mut self.command_encoder else {
+            return Err(Error::EncoderIsNone);
+        };
+        encoder.copy_buffer_to_buffer(src, range.start, dst, range.start, len);
 
-            staging_buffer.mapped = true;
-        }
-        self
+        Ok(())
     }
 
-    /// Read data from `target` staging buffer, return raw bytes
     #[inline]
-    pub fn try_read_raw<'a>(&'a self, target: &str) -> Result<(impl Deref<Target = [u8]> + 'a)> {
-        let Some(staging_buffer) = &self.staging_buffers.get(target) else {
-            return Err(Error::StagingBufferNotFound(target.to_owned()));
+    fn clear(&mut self, name: &str) -> Result<()> {
+        let Some(buffer) = self.buffers.get(name) else {
+            return Err(Error::BufferNotFound(name.to_owned()));
         };
 
-        let result = staging_buffer.buffer.slice(..).get_mapped_range();
-
-        Ok(result)
-    }
+        let Some(encoder) = &mut self.command_encoder else {
+            return Err(Error::EncoderIsNone);
+        };
+        encoder.clear_buffer(buffer, 0, None);
 
-    /// Read data from `target` staging buffer, return raw bytes
-    /// Panics on error.
-    #[inline]
-    pub fn read_raw<'a>(&'a self, target: &str) -> (impl Deref<Target = [u8]> + 'a) {
-        self.try_read_raw(target).unwrap()
+        Ok(())
     }
 
-    /// Try Read data from `target` staging buffer, return a single `B: Pod`
-    #[inline]
-    pub fn try_read<B: AnyBitPattern>(&self, target: &str) -> Result<B> {
-        let result = *from_bytes::<B>(&self.try_read_raw(target)?);
-        Ok(result)
+    fn run_step(&mut self, step: &Step) -> WorkerResult<W> {
+        match step {
+            Step::ComputePass(compute_pass) => self.dispatch(compute_pass).map_err(|err| {
+                ComputeWorkerError::from(err).with_shader(compute_pass.shader_name)
+            })?,
+            Step::Swap(a, b) => self.swap(a, b)?,
+            Step::Copy { src, dst, range } => self.copy(src, dst, range.clone())?,
+            Step::Clear(name) => self.clear(name)?,
+            Step::Repeat { count, steps } => {
+                for _ in 0..*count {
+                    self.run_steps(steps)?;
+                }
+            }
+            Step::If { flag, steps } => {
+                if self.flags.get(flag).copied().unwrap_or_default() {
+                    self.run_steps(steps)?;
+                }
+            }
+        }
+        Ok(())
     }
 
-    /// Try Read data from `target` staging buffer, return a single `B: Pod`
-    /// In case of error, this function will panic.
-    #[inline]
-    pub fn read<B: AnyBitPattern>(&self, target: &str) -> B {
-        self.try_read(target).unwrap()
+    fn run_steps(&mut self, steps: &[Step]) -> WorkerResult<W> {
+        for step in steps {
+            self.run_step(step)?;
+        }
+        Ok(())
     }
 
-    /// Try Read data from `target` staging buffer, return a vector of `B: Pod`
+    /// Copy the staging buffers that should be read back this run into `slot`.
     #[inline]
-    pub fn try_read_vec<B: AnyBitPattern>(&self, target: &str) -> Result<Vec<B>> {
-        let bytes = self.try_read_raw(target)?;
-        Ok(cast_slice::<u8, B>(&bytes).to_vec())
+    fn read_staging_buffers(&mut self, slot: usize) -> Result<Vec<String>> {
+        let Some(encoder) = &mut self.command_encoder else {
+            return Err(Error::EncoderIsNone);
+        };
+
+        let mut names = vec![];
+        for (name, staging_buffer) in self.staging_buffers.iter_mut() {
+            if !staging_buffer.should_read(self.runs) {
+                continue;
+            }
+
+            let staging = &staging_buffer.slots[slot];
+            if let Some(buffer) = self.buffers.get(name) {
+                encoder.copy_buffer_to_buffer(buffer, 0, staging, 0, staging.size());
+            } else if let Some(texture) = self.textures.get(name) {
+                texture.copy_to_buffer(encoder, staging)?;
+            } else {
+                return Err(Error::ResourceNotFound(name.to_owned()));
+            }
+            names.push(name.clone());
+        }
+
+        if let Some(timings) = &mut self.timings {
+            timings.resolve(encoder, slot);
+        }
+        Ok(names)
+    }
+
+    #[inline]
+    fn map_staging_buffers(&mut self, slot: usize, names: &[String]) -> &mut Self {
+        let pending_maps = Arc::new(PendingMaps::default());
+        pending_maps.work_done(&self.render_queue);
+
+        for name in names {
+            let staging_buffer = self.staging_buffers.get_mut(name).unwrap();
+            pending_maps.map(staging_buffer.slots[slot].slice(..));
+            staging_buffer.mapped[slot] = true;
+        }
+
+        if let Some(timings) = &mut self.timings {
+            timings.map(slot, &pending_maps);
+        }
+        self.in_flight.push_back(InFlightRun {
+            run: self.runs,
+            slot,
+            pending_maps,
+        });
+        self
+    }
+
+    /// Return the staging buffer slot that can currently be read from.
+    #[inline]
+    fn mapped_staging_buffer(&self, name: &str) -> Result<&Buffer> {
+        let Some(staging_buffer) = self.staging_buffers.get(name) else {
+            return Err(Error::StagingBufferNotFound(name.to_owned()));
+        };
+
+        match self.mapped_slot {
+            Some(slot) if staging_buffer.mapped[slot] => Ok(&staging_buffer.slots[slot]),
+            _ => Err(Error::StagingBufferNotMapped(name.to_owned())),
+        }
+    }
+
+    /// Read data from `target` staging buffer, return raw bytes
+    #[inline]
+    pub fn try_read_raw<'a, H: ResourceHandle + ?Sized>(
+        &'a self,
+        target: &H,
+    ) -> Result<impl Deref<Target = [u8]> + 'a> {
+        let staging_buffer = self.mapped_staging_buffer(target.name())?;
+
+        let result = staging_buffer.slice(..).get_mapped_range();
+
+        Ok(result)
+    }
+
+    /// Read data from `target` staging buffer, return raw bytes
+    /// Panics on error.
+    #[inline]
+    pub fn read_raw<'a, H: ResourceHandle + ?Sized>(
+        &'a self,
+        target: &H,
+    ) -> impl Deref<Target = [u8]> + 'a {
+        self.try_read_raw(target).unwrap()
+    }
+
+    /// Try Read data from `target` staging buffer, return a single `B: Pod`
+    #[inline]
+    pub fn try_read<B: AnyBitPattern>(&self, target: &BufferHandle<B>) -> Result<B> {
+        let result = *from_bytes::<B>(&self.try_read_raw(target)?);
+        Ok(result)
     }
 
-    /// Try Read data from `target` staging buffer, return a vector of `B: Pod`
+    /// Try Read data from `target` staging buffer, return a single `B: Pod`
     /// In case of error, this function will panic.
     #[inline]
-    pub fn read_vec<B: AnyBitPattern>(&self, target: &str) -> Vec<B> {
-        self.try_read_vec(target).unwrap()
+    pub fn read<B: AnyBitPattern>(&self, target: &BufferHandle<B>) -> B {
+        self.try_read(target).unwrap()
     }
 
-    /// Write data to `target` buffer.
+    /// Try Read data from `target` staging buffer, return a view derefing to `&[B]`.
+    /// The data isn't copied out of the mapped buffer.
     #[inline]
-    pub fn try_write<T: NoUninit>(&mut self, target: &str, data: &T) -> Result<()> {
-        let Some(buffer) = &self.buffers.get(target) else {
-            return Err(Error::BufferNotFound(target.to_owned()));
+    pub fn try_read_slice<B: AnyBitPattern>(
+        &self,
+        target: &BufferHandle<Vec<B>>,
+    ) -> Result<MappedSlice<'_, B>> {
+        let staging_buffer = self.mapped_staging_buffer(target.name())?;
+        let size = staging_buffer.size() as usize;
+
+        Ok(MappedSlice::new(
+            staging_buffer.slice(..).get_mapped_range(),
+            0..size - size % size_of::<B>(),
+        ))
+    }
+
+    /// Try Read data from `target` staging buffer, return a view derefing to `&[B]`.
+    /// In case of error, this function will panic.
+    #[inline]
+    pub fn read_slice<B: AnyBitPattern>(
+        &self,
+        target: &BufferHandle<Vec<B>>,
+    ) -> MappedSlice<'_, B> {
+        self.try_read_slice(target).unwrap()
+    }
+
+    /// Try Read the elements in `range` from `target` staging buffer,
+    /// return a view derefing to `&[B]`.
+    #[inline]
+    pub fn try_read_range<B: AnyBitPattern>(
+        &self,
+        target: &BufferHandle<Vec<B>>,
+        range: Range<usize>,
+    ) -> Result<MappedSlice<'_, B>> {
+        let staging_buffer = self.mapped_staging_buffer(target.name())?;
+
+        let element_size = size_of::<B>() as u64;
+        let bytes = checked_byte_range(
+            target.name(),
+            element_bytes(range.start, element_size)..element_bytes(range.end, element_size),
+            staging_buffer.size(),
+        )?;
+
+        Ok(MappedSlice::new(
+            staging_buffer.slice(..).get_mapped_range(),
+            bytes.start as usize..bytes.end as usize,
+        ))
+    }
+
+    /// Try Read the elements in `range` from `target` staging buffer,
+    /// return a view derefing to `&[B]`.
+    /// In case of error, this function will panic.
+    #[inline]
+    pub fn read_range<B: AnyBitPattern>(
+        &self,
+        target: &BufferHandle<Vec<B>>,
+        range: Range<usize>,
+    ) -> MappedSlice<'_, B> {
+        self.try_read_range(target, range).unwrap()
+    }
+
+    /// Read `target` texture from its staging buffer, tightly packed.
+    pub fn try_read_texture(&self, target: &TextureHandle) -> Result<Vec<u8>> {
+        let Some(texture) = self.textures.get(target.name()) else {
+            return Err(Error::TextureNotFound(target.name().to_owned()));
+        };
+        let staging_buffer = self.mapped_staging_buffer(target.name())?;
+
+        texture.unpad(&staging_buffer.slice(..).get_mapped_range())
+    }
+
+    /// Read `target` texture from its staging buffer, tightly packed.
+    /// In case of error, this function will panic.
+    pub fn read_texture(&self, target: &TextureHandle) -> Vec<u8> {
+        self.try_read_texture(target).unwrap()
+    }
+
+    /// Read `target` texture from its staging buffer into a new [`Image`],
+    /// e.g. to display it by inserting it in `Assets<Image>`.
+    pub fn try_read_image(&self, target: &TextureHandle) -> Result<Image> {
+        let Some(texture) = self.textures.get(target.name()) else {
+            return Err(Error::TextureNotFound(target.name().to_owned()));
         };
+        let staging_buffer = self.mapped_staging_buffer(target.name())?;
 
-        let bytes = bytes_of(data);
+        texture.to_image(&staging_buffer.slice(..).get_mapped_range())
+    }
+
+    /// Read `target` texture from its staging buffer into a new [`Image`].
+    /// In case of error, this function will panic.
+    pub fn read_image(&self, target: &TextureHandle) -> Image {
+        self.try_read_image(target).unwrap()
+    }
 
-        self.render_queue.write_buffer(buffer, 0, bytes);
+    /// Copy `target` back to the CPU after the next run, whatever its [`ReadbackPolicy`].
+    pub fn try_request_readback<H: ResourceHandle + ?Sized>(&mut self, target: &H) -> Result<()> {
+        let Some(staging_buffer) = self.staging_buffers.get_mut(target.name()) else {
+            return Err(Error::StagingBufferNotFound(target.name().to_owned()));
+        };
 
+        staging_buffer.requested = true;
         Ok(())
     }
 
-    /// Write data to `target` buffer.
+    /// Copy `target` back to the CPU after the next run, whatever its [`ReadbackPolicy`].
     /// In case of error, this function will panic.
-    #[inline]
-    pub fn write<T: NoUninit>(&mut self, target: &str, data: &T) {
-        self.try_write(target, data).unwrap()
+    pub fn request_readback<H: ResourceHandle + ?Sized>(&mut self, target: &H) {
+        self.try_request_readback(target).unwrap()
     }
 
-    /// Write data to `target` buffer.
+    /// Time the next run, whatever the [`ReadbackPolicy`] given to
+    /// [`AppComputeWorkerBuilder::timings_with_policy`]. Does nothing if timings aren't enabled.
+    pub fn request_timings(&mut self) {
+        if let Some(timings) = &mut self.timings {
+            timings.requested = true;
+        }
+    }
+
+    /// Change the [`ReadbackPolicy`] of `target` staging buffer.
+    pub fn try_set_readback_policy<H: ResourceHandle + ?Sized>(
+        &mut self,
+        target: &H,
+        policy: ReadbackPolicy,
+    ) -> Result<()> {
+        let Some(staging_buffer) = self.staging_buffers.get_mut(target.name()) else {
+            return Err(Error::StagingBufferNotFound(target.name().to_owned()));
+        };
+
+        staging_buffer.policy = policy;
+        Ok(())
+    }
+
+    /// Change the [`ReadbackPolicy`] of `target` staging buffer.
+    /// In case of error, this function will panic.
+    pub fn set_readback_policy<H: ResourceHandle + ?Sized>(
+        &mut self,
+        target: &H,
+        policy: ReadbackPolicy,
+    ) {
+        self.try_set_readback_policy(target, policy).unwrap()
+    }
+
+    /// Request the contents of `target` staging buffer from the next run that completes.
+    ///
+    /// Unlike [`Self::read_slice`], there is no need to check [`Self::ready`]:
+    /// the returned [`Readback`] can be awaited, or polled with [`Readback::try_take`].
+    pub fn try_read_async<B: AnyBitPattern + Send>(
+        &mut self,
+        target: &BufferHandle<Vec<B>>,
+    ) -> Result<Readback<B>> {
+        self.try_request_readback(target)?;
+
+        let (readback, pending) = Readback::new();
+        self.pending_readbacks
+            .push((target.name().to_owned(), pending));
+
+        Ok(readback)
+    }
+
+    /// Request the contents of `target` staging buffer from the next run that completes.
+    /// In case of error, this function will panic.
+    pub fn read_async<B: AnyBitPattern + Send>(
+        &mut self,
+        target: &BufferHandle<Vec<B>>,
+    ) -> Readback<B> {
+        self.try_read_async(target).unwrap()
+    }
+
+    /// Write `bytes` to `target` buffer at `offset`, checking it fits in the buffer.
     #[inline]
-    pub fn try_write_slice<T: NoUninit>(&mut self, target: &str, data: &[T]) -> Result<()> {
+    fn write_bytes(&mut self, target: &str, offset: u64, bytes: &[u8]) -> Result<()> {
         let Some(buffer) = &self.buffers.get(target) else {
             return Err(Error::BufferNotFound(target.to_owned()));
         };
 
-        let bytes = cast_slice(data);
+        let end = offset.saturating_add(bytes.len() as u64);
+        let range = checked_byte_range(target, offset..end, buffer.size())?;
+        if range.start % COPY_BUFFER_ALIGNMENT != 0
+            || bytes.len() as u64 % COPY_BUFFER_ALIGNMENT != 0
+        {
+            return Err(Error::UnalignedBufferAccess(target.to_owned(), range));
+        }
 
-        self.render_queue.write_buffer(buffer, 0, bytes);
+        self.render_queue.write_buffer(buffer, offset, bytes);
 
         Ok(())
     }
 
+    /// Write data to `target` buffer.
+    #[inline]
+    pub fn try_write<T: NoUninit>(&mut self, target: &BufferHandle<T>, data: &T) -> Result<()> {
+        self.write_bytes(target.name(), 0, bytes_of(data))
+    }
+
     /// Write data to `target` buffer.
     /// In case of error, this function will panic.
     #[inline]
-    pub fn write_slice<T: NoUninit>(&mut self, target: &str, data: &[T]) {
-        self.try_write_slice(target, data).unwrap()
+    pub fn write<T: NoUninit>(&mut self, target: &BufferHandle<T>, data: &T) {
+        self.try_write(target, data).unwrap()
     }
 
-    fn submit(&mut self) -> &mut Self {
-        let encoder = self.command_encoder.take().unwrap();
-        self.render_queue.submit(Some(encoder.finish()));
-        self.state = WorkerState::Working;
-        self
+    /// Write data to `target` buffer.
+    #[inline]
+    pub fn try_write_slice<T: NoUninit>(
+        &mut self,
+        target: &BufferHandle<Vec<T>>,
+        data: &[T],
+    ) -> Result<()> {
+        self.try_write_at(target, 0, data)
     }
 
+    /// Write data to `target` buffer.
+    /// In case of error, this function will panic.
     #[inline]
-    fn poll(&self) -> bool {
-        self.render_device
-            .wgpu_device()
-            .poll(wgpu::MaintainBase::Wait)
+    pub fn write_slice<T: NoUninit>(&mut self, target: &BufferHandle<Vec<T>>, data: &[T]) {
+        self.try_write_slice(target, data).unwrap()
     }
 
-    /// Check if the worker is ready to be read from.
+    /// Write data to `target` buffer, starting at element `element_index`.
+    ///
+    /// The written bytes must be 4 bytes aligned, as required by wgpu.
     #[inline]
-    pub fn ready(&self) -> bool {
-        self.state == WorkerState::FinishedWorking
+    pub fn try_write_at<T: NoUninit>(
+        &mut self,
+        target: &BufferHandle<Vec<T>>,
+        element_index: usize,
+        data: &[T],
+    ) -> Result<()> {
+        let offset = element_bytes(element_index, size_of::<T>() as u64);
+        self.write_bytes(target.name(), offset, cast_slice(data))
     }
 
-    /// Tell the worker to execute the compute shader at the end of the current frame
+    /// Write data to `target` buffer, starting at element `element_index`.
+    /// In case of error, this function will panic.
     #[inline]
-    pub fn execute(&mut self) {
-        match self.run_mode {
-            RunMode::Continuous => {}
-            RunMode::OneShot(_) => self.run_mode = RunMode::OneShot(true),
+    pub fn write_at<T: NoUninit>(
+        &mut self,
+        target: &BufferHandle<Vec<T>>,
+        element_index: usize,
+        data: &[T],
+    ) {
+        self.try_write_at(target, element_index, data).unwrap()
+    }
+
+    /// Reallocate `target` buffer, and its staging buffers, to hold `new_len` elements.
+    ///
+    /// If `preserve_contents` is true, the first elements are copied over to the new
+    /// buffer on the GPU before the next run, otherwise the buffer is zeroed.
+    /// Passes added with [`AppComputeWorkerBuilder::add_pass_for_elements`] that bind `target`
+    /// and were sized after its previous length follow the new one.
+    ///
+    /// Runs still in flight aren't waited for, the results they read back keep the previous length.
+    /// Buffers shared with other workers through [`SharedComputeBuffers`](super::shared::SharedComputeBuffers)
+    /// can't be resized.
+    pub fn try_resize<T>(
+        &mut self,
+        target: &BufferHandle<Vec<T>>,
+        new_len: usize,
+        preserve_contents: bool,
+    ) -> Result<()> {
+        let name = target.name();
+        let Some(buffer) = self.buffers.get(name) else {
+            return Err(Error::BufferNotFound(name.to_owned()));
+        };
+        if self.shared_buffers.contains(name) {
+            return Err(Error::SharedBufferResize(name.to_owned()));
+        }
+        if new_len == 0 {
+            return Err(Error::EmptyResize(name.to_owned()));
         }
+
+        if preserve_contents && !buffer.usage().contains(BufferUsages::COPY_SRC) {
+            return Err(Error::BufferNotCopyable(name.to_owned()));
+        }
+        // Passes following the buffer dispatch one invocation per element
+        let Ok(new_count) = u32::try_from(new_len) else {
+            return Err(Error::TooManyElements(name.to_owned(), new_len));
+        };
+
+        let element_size = size_of::<T>() as u64;
+        let old_size = buffer.size();
+        // The size of a resized buffer is padded, so its length is kept instead
+        let old_len = match self.buffer_lens.get(name) {
+            Some(&len) => Some(len),
+            None => old_size.checked_div(element_size).map(|len| len as usize),
+        };
+        let max_size = self.render_device.limits().max_buffer_size;
+        let Some(new_size) = (new_len as u64)
+            .checked_mul(element_size)
+            .and_then(|size| size.checked_next_multiple_of(COPY_BUFFER_ALIGNMENT))
+            .filter(|&size| size <= max_size)
+        else {
+            let range = 0..element_bytes(new_len, element_size);
+            return Err(Error::BufferOutOfBounds(name.to_owned(), range, max_size));
+        };
+
+        let new_buffer = self.render_device.create_buffer(&BufferDescriptor {
+            label: Some(name),
+            size: new_size,
+            usage: buffer.usage(),
+            mapped_at_creation: false,
+        });
+
+        if preserve_contents {
+            let Some(encoder) = &mut self.command_encoder else {
+                return Err(Error::EncoderIsNone);
+            };
+            let copy_size = old_size.min(new_size) / COPY_BUFFER_ALIGNMENT * COPY_BUFFER_ALIGNMENT;
+            encoder.copy_buffer_to_buffer(buffer, 0, &new_buffer, 0, copy_size);
+        }
+
+        let old_id = ResourceId::Buffer(buffer.id());
+        self.bind_groups
+            .retain(|(_, _, resources), _| resources.iter().all(|(_, id)| *id != old_id));
+        self.buffers.insert(name.to_owned(), new_buffer);
+
+        if let Some(staging_buffer) = self.staging_buffers.get_mut(name) {
+            staging_buffer.reallocate(&self.render_device, name, new_size);
+        }
+
+        if let Some(old_len) = old_len {
+            resize_dispatches(&mut self.steps, name, old_len, new_count);
+        }
+        self.buffer_lens.insert(name.to_owned(), new_len);
+
+        Ok(())
     }
 
-    #[inline]
-    fn ready_to_execute(&self) -> bool {
-        (self.state != WorkerState::Working) && (self.run_mode != RunMode::OneShot(false))
+    /// Reallocate `target` buffer, and its staging buffers, to hold `new_len` elements.
+    /// In case of error, this function will panic.
+    pub fn resize<T>(
+        &mut self,
+        target: &BufferHandle<Vec<T>>,
+        new_len: usize,
+        preserve_contents: bool,
+    ) {
+        self.try_resize(target, new_len, preserve_contents).unwrap()
     }
 
-    pub(crate) fn run(mut worker: ResMut<Self>) {
-        if worker.ready() {
-            worker.state = WorkerState::Available;
+    /// Read back every buffer of the worker, not only those with a staging buffer,
+    /// e.g. to save the state of a simulation and [`restore`](Self::restore) it later.
+    ///
+    /// This submits the commands recorded since the last run, e.g. by [`Self::resize`],
+    /// and waits for the GPU to finish them. Textures aren't saved, so this fails with
+    /// [`Error::InvalidSnapshot`] if the worker has any.
+    pub fn try_snapshot(&mut self) -> Result<WorkerSnapshot> {
+        if let Some(name) = self.textures.keys().min() {
+            return Err(Error::InvalidSnapshot(format!(
+                "texture {name} can't be saved, only buffers are"
+            )));
         }
 
-        if worker.ready_to_execute() {
-            // Workaround for interior mutability
-            for i in 0..worker.steps.len() {
-                let result = match worker.steps[i] {
-                    Step::ComputePass(_) => worker.dispatch(i),
-                    Step::Swap(_, _) => worker.swap(i),
-                };
+        // The commands of a failed run are dropped by `reset` instead
+        let mut command_buffers = Vec::with_capacity(2);
+        if !self.failed() {
+            let encoder = self
+                .render_device
+                .create_command_encoder(&CommandEncoderDescriptor { label: None });
+            let Some(pending) = self.command_encoder.replace(encoder) else {
+                return Err(Error::EncoderIsNone);
+            };
+            command_buffers.push(pending.finish());
+        }
 
-                if let Err(err) = result {
-                    match err {
-                        Error::PipelineNotReady => return,
-                        _ => panic!("{:?}", err),
-                    }
-                }
+        let mut encoder = self
+            .render_device
+            .create_command_encoder(&CommandEncoderDescriptor { label: None });
+
+        let mut readbacks = Vec::with_capacity(self.buffers.len());
+        for (name, buffer) in &self.buffers {
+            if !buffer.usage().contains(BufferUsages::COPY_SRC) {
+                return Err(Error::BufferNotCopyable(name.clone()));
             }
+            let readback = self.render_device.create_buffer(&BufferDescriptor {
+                label: Some(name),
+                size: buffer.size(),
+                usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+            encoder.copy_buffer_to_buffer(buffer, 0, &readback, 0, buffer.size());
+            readbacks.push((name, readback));
+        }
+        command_buffers.push(encoder.finish());
+        self.render_queue.submit(command_buffers);
 
-            worker.read_staging_buffers().unwrap();
-            worker.submit();
-            worker.map_staging_buffers();
+        let pending_maps = Arc::new(PendingMaps::default());
+        for (_, readback) in &readbacks {
+            pending_maps.map(readback.slice(..));
+        }
+        self.render_device
+            .wgpu_device()
+            .poll(wgpu::MaintainBase::Wait);
+        if let Some(err) = pending_maps.error.lock().take() {
+            return Err(Error::BufferMapFailed(err.to_string()));
         }
 
-        if worker.run_mode != RunMode::OneShot(false) && worker.poll() {
-            worker.state = WorkerState::FinishedWorking;
-            worker.command_encoder = Some(
-                worker
-                    .render_device
-                    .create_command_encoder(&CommandEncoderDescriptor { label: None }),
-            );
+        let buffers = readbacks
+            .into_iter()
+            .map(|(name, readback)| {
+                let bytes = readback.slice(..).get_mapped_range().to_vec();
+                readback.unmap();
+                (name.clone(), bytes)
+            })
+            .collect();
+
+        Ok(WorkerSnapshot::new(std::any::type_name::<W>(), buffers))
+    }
+
+    /// Read back every buffer of the worker.
+    /// In case of error, this function will panic.
+    pub fn snapshot(&mut self) -> WorkerSnapshot {
+        self.try_snapshot().unwrap()
+    }
+
+    /// Upload the buffers saved in `snapshot`, before the next run.
+    ///
+    /// The snapshot must have been taken from a worker of the same type, and every buffer
+    /// of the snapshot must exist in the worker with the same size, nothing is written otherwise.
+    /// Buffers missing from the snapshot are left as is.
+    pub fn try_restore(&mut self, snapshot: &WorkerSnapshot) -> Result<()> {
+        let worker = std::any::type_name::<W>();
+        if snapshot.worker() != worker {
+            return Err(Error::InvalidSnapshot(format!(
+                "taken from {}, not {worker}",
+                snapshot.worker()
+            )));
+        }
 
-            match worker.run_mode {
-                RunMode::Continuous => {}
-                RunMode::OneShot(_) => worker.run_mode = RunMode::OneShot(false),
+        for (name, bytes) in snapshot.buffers() {
+            let Some(buffer) = self.buffers.get(name) else {
+                return Err(Error::BufferNotFound(name.to_owned()));
             };
+            if buffer.size() != bytes.len() as u64 {
+                return Err(Error::SnapshotSizeMismatch(
+                    name.to_owned(),
+                    buffer.size(),
+                    bytes.len() as u64,
+                ));
+            }
+        }
+
+        for (name, bytes) in snapshot.buffers() {
+            self.render_queue
+                .write_buffer(&self.buffers[name], 0, bytes);
         }
+
+        Ok(())
     }
 
-    pub(crate) fn unmap_all(mut worker: ResMut<Self>) {
-        for (_, staging_buffer) in &mut worker.staging_buffers {
-            if staging_buffer.mapped {
-                staging_buffer.buffer.unmap();
-                staging_buffer.mapped = false;
+    /// Upload the buffers saved in `snapshot`, before the next run.
+    /// In case of error, this function will panic.
+    pub fn restore(&mut self, snapshot: &WorkerSnapshot) {
+        self.try_restore(snapshot).unwrap()
+    }
+
+    fn submit(&mut self) -> &mut Self {
+        let encoder = self.command_encoder.take().unwrap();
+        self.render_queue.submit(Some(encoder.finish()));
+        self.state = WorkerState::Working;
+        self.command_encoder = Some(
+            self.render_device
+                .create_command_encoder(&CommandEncoderDescriptor { label: None }),
+        );
+        self
+    }
+
+    /// Poll the device and check if a run has been mapped.
+    ///
+    /// With `wait`, this blocks until the GPU is done,
+    /// otherwise the results of a run will be available a few frames later.
+    #[inline]
+    fn poll(&mut self, wait: bool) -> Result<bool> {
+        if self.in_flight.is_empty() {
+            return Ok(false);
+        }
+
+        let maintain = if wait {
+            wgpu::MaintainBase::Wait
+        } else {
+            wgpu::MaintainBase::Poll
+        };
+        self.render_device.wgpu_device().poll(maintain);
+
+        let mut finished = None;
+        let mut last_run = None;
+        while self.in_flight.front().is_some_and(InFlightRun::is_mapped) {
+            let run = self.in_flight.pop_front().unwrap();
+            if let Some(err) = run.take_error() {
+                if let Some(timings) = &mut self.timings {
+                    timings.discard(run.slot);
+                }
+                self.unmap_slot(run.slot);
+                if let Some(previous) = finished {
+                    self.unmap_slot(previous);
+                }
+                return Err(Error::BufferMapFailed(err.to_string()));
+            }
+
+            if let Some(timings) = &mut self.timings {
+                timings.collect(run.slot);
+            }
+            // Before its slot is unmapped, in case a later run doesn't read the same buffers
+            self.fulfill_readbacks(run.slot);
+
+            // Only keep the most recent results around
+            if let Some(previous) = finished.replace(run.slot) {
+                self.unmap_slot(previous);
+            }
+            last_run = Some(run.run);
+        }
+
+        let Some(slot) = finished else {
+            return Ok(false);
+        };
+        // The results kept from an earlier run are replaced
+        if let Some(previous) = self.mapped_slot.replace(slot) {
+            self.unmap_slot(previous);
+        }
+        self.last_run = last_run;
+
+        if let Some(run) = last_run {
+            for callback in self.on_finished.clone() {
+                callback(self, run);
             }
         }
+
+        Ok(true)
     }
 
-    pub(crate) fn extract_pipelines(
+    /// Specialize every pass of `S` with `shader_defs`, in addition to [`ComputeShader::shader_defs`],
+    /// instead of the ones given to [`AppComputeWorkerBuilder::add_pass_with_defs`].
+    ///
+    /// Pipelines are compiled for the defs no pass used yet,
+    /// the runs are skipped until they are ready.
+    pub fn set_shader_defs<S: ComputeShader>(&mut self, shader_defs: &[ShaderDefVal]) {
+        self.set_shader_defs_of(S::TYPE_UUID, shader_defs);
+    }
+
+    /// Every pass of the worker, in the order they were added.
+    pub(crate) fn compute_passes(&self) -> Vec<ComputePass> {
+        let mut passes = vec![];
+        for_each_pass_mut(&mut self.steps.clone(), |compute_pass| {
+            passes.push(compute_pass.clone());
+        });
+        passes
+    }
+
+    fn set_shader_defs_of(&mut self, shader_uuid: Uuid, shader_defs: &[ShaderDefVal]) {
+        for_each_pass_mut(&mut self.steps, |compute_pass| {
+            if compute_pass.shader_uuid == shader_uuid && compute_pass.shader_defs != shader_defs {
+                compute_pass.shader_defs = shader_defs.to_vec();
+                self.respecialized = true;
+            }
+        });
+    }
+
+    pub(crate) fn apply_shader_defs(
         mut worker: ResMut<Self>,
-        pipeline_cache: Res<AppPipelineCache>,
+        shader_defs: Res<ComputeShaderDefs<W>>,
     ) {
-        for (uuid, cached_id) in &worker.cached_pipeline_ids.clone() {
-            let Some(pipeline) = worker.pipelines.get(uuid) else {
-                continue;
-            };
+        worker.set_all_shader_defs(&shader_defs);
+    }
+
+    pub(crate) fn apply_component_shader_defs(
+        mut workers: Query<&mut Self>,
+        shader_defs: Res<ComputeShaderDefs<W>>,
+    ) {
+        for mut worker in &mut workers {
+            worker.set_all_shader_defs(&shader_defs);
+        }
+    }
 
-            if pipeline.is_some() {
+    /// Specialize the passes of each shader of `shader_defs`.
+    pub(crate) fn set_all_shader_defs(&mut self, shader_defs: &ComputeShaderDefs<W>) {
+        for (shader_uuid, shader_defs) in &shader_defs.shader_defs {
+            self.set_shader_defs_of(*shader_uuid, shader_defs);
+        }
+    }
+
+    /// Whether the worker binds a buffer of [`SharedComputeBuffers`](super::shared::SharedComputeBuffers).
+    #[inline]
+    pub(crate) fn uses_shared_buffers(&self) -> bool {
+        !self.shared_buffers.is_empty()
+    }
+
+    /// Whether the worker was built with [`AppComputeWorkerBuilder::timings`].
+    #[inline]
+    pub(crate) fn has_timings(&self) -> bool {
+        self.timings.is_some()
+    }
+
+    /// Queue the pipelines of the passes built or specialized since the last call.
+    pub(crate) fn queue_specialized_pipelines(&mut self, pipeline_cache: &AppPipelineCache) {
+        if !std::mem::take(&mut self.respecialized) {
+            return;
+        }
+
+        let mut keys = HashSet::default();
+        let _ = try_for_each_pass(&self.steps, &mut |compute_pass| {
+            keys.insert(compute_pass.pipeline_key());
+            Ok(())
+        });
+
+        for key in keys {
+            if self.cached_pipeline_ids.contains_key(&key) {
+                continue;
+            }
+            let Some(descriptor) = self.pipeline_descriptors.get(&key.shader_uuid) else {
                 continue;
             };
 
-            let cached_id = *cached_id;
+            let cached_id = pipeline_cache.queue_app_compute_pipeline(key.descriptor(descriptor));
+            self.cached_pipeline_ids.insert(key.clone(), cached_id);
+            self.pipelines.insert(key, None);
+        }
+    }
 
-            worker.pipelines.insert(
-                *uuid,
-                pipeline_cache.get_compute_pipeline(cached_id).cloned(),
-            );
+    /// Change the number of elements processed by the passes of `S`
+    /// added with [`AppComputeWorkerBuilder::add_pass_for_elements`].
+    /// Their dispatch size is updated from the next run.
+    pub fn set_element_count<S: ComputeShader>(&mut self, count: u32) {
+        for_each_pass_mut(&mut self.steps, |compute_pass| {
+            if let Dispatch::Elements(elements) = &mut compute_pass.dispatch {
+                if compute_pass.shader_uuid == S::TYPE_UUID {
+                    *elements = count;
+                }
+            }
+        });
+    }
+
+    /// Set `flag`, which the steps added with [`AppComputeWorkerBuilder::add_if`] depend on.
+    pub fn try_set_flag(&mut self, flag: &str, value: bool) -> Result<()> {
+        let Some(current) = self.flags.get_mut(flag) else {
+            return Err(Error::FlagNotFound(flag.to_owned()));
+        };
+        *current = value;
+        Ok(())
+    }
+
+    /// Set `flag`, which the steps added with [`AppComputeWorkerBuilder::add_if`] depend on.
+    /// In case of error, this function will panic.
+    pub fn set_flag(&mut self, flag: &str, value: bool) {
+        self.try_set_flag(flag, value).unwrap()
+    }
+
+    /// Set the push constants of a pass added with
+    /// [`AppComputeWorkerBuilder::add_pass_with_push_constants`], from the next run.
+    pub fn try_set_push_constants<T: NoUninit>(
+        &mut self,
+        pass: &PushConstantsHandle<T>,
+        data: &T,
+    ) -> Result<()> {
+        let mut found = false;
+        try_for_each_pass_mut(&mut self.steps, &mut |compute_pass| {
+            if compute_pass.id == pass.pass() {
+                if compute_pass.push_constants.len() != size_of::<T>() {
+                    return Err(Error::InvalidStep(format!("{compute_pass:?}")));
+                }
+                compute_pass.push_constants.copy_from_slice(bytes_of(data));
+                found = true;
+            }
+            Ok(())
+        })?;
+
+        match found {
+            true => Ok(()),
+            false => Err(Error::InvalidStep(format!("{pass:?}"))),
         }
     }
+
+    /// Set the push constants of a pass added with
+    /// [`AppComputeWorkerBuilder::add_pass_with_push_constants`], from the next run.
+    /// In case of error, this function will panic.
+    pub fn set_push_constants<T: NoUninit>(&mut self, pass: &PushConstantsHandle<T>, data: &T) {
+        self.try_set_push_constants(pass, data).unwrap()
+    }
+
+    /// Check if the worker is ready to be read from.
+    #[inline]
+    pub fn ready(&self) -> bool {
+        self.state == WorkerState::FinishedWorking
+    }
+
+    /// Index of the last run whose results were mapped, counting from 0.
+    ///
+    /// With [`AppComputeWorkerBuilder::keep_last_results`], they can be read
+    /// until the next run completes, even when the worker isn't [`ready`](Self::ready).
+    #[inline]
+    pub fn last_completed_run(&self) -> Option<u64> {
+        self.last_run
+    }
+
+    /// Tell the worker to execute the compute shader at the end of the current frame
+    #[inline]
+    pub fn execute(&mut self) {
+        match self.run_mode {
+            RunMode::Continuous => {}
+            RunMode::OneShot(_) => self.run_mode = RunMode::OneShot(true),
+        }
+    }
+
+    #[inline]
+    fn ready_to_execute(&self) -> bool {
+        !self.free_slots.is_empty() && (self.run_mode != RunMode::OneShot(false))
+    }
+
+    /// Check if the worker failed, see [`ComputeWorkerError<W>`].
+    #[inline]
+    pub fn failed(&self) -> bool {
+        self.state == WorkerState::Failed
+    }
+
+    /// Run again after a [`ComputeWorkerError<W>`], e.g. once the faulty shader has been fixed.
+    ///
+    /// The commands recorded by the failed run are dropped,
+    /// and the pipelines that failed are checked again when they are recompiled.
+    pub fn reset(&mut self) {
+        if !self.failed() {
+            return;
+        }
+
+        self.command_encoder = Some(
+            self.render_device
+                .create_command_encoder(&CommandEncoderDescriptor { label: None }),
+        );
+        if let Some(timings) = &mut self.timings {
+            timings.discard_recording();
+        }
+        self.state = WorkerState::Available;
+    }
+
+    /// Move to [`WorkerState::Failed`] because of `err`.
+    #[inline]
+    fn fail(&mut self, err: ComputeWorkerError<W>) -> ComputeWorkerError<W> {
+        self.state = WorkerState::Failed;
+        err
+    }
+
+    pub(crate) fn run(
+        mut commands: Commands,
+        mut worker: ResMut<Self>,
+        mut errors: EventWriter<ComputeWorkerError<W>>,
+        mut finished: EventWriter<ComputeWorkerFinished<W>>,
+    ) {
+        worker.run_and_notify(None, &mut commands, &mut errors, &mut finished);
+    }
+
+    pub(crate) fn run_components(
+        mut commands: Commands,
+        mut workers: Query<(Entity, &mut Self)>,
+        mut errors: EventWriter<ComputeWorkerError<W>>,
+        mut finished: EventWriter<ComputeWorkerFinished<W>>,
+    ) {
+        for (entity, mut worker) in &mut workers {
+            worker.run_and_notify(Some(entity), &mut commands, &mut errors, &mut finished);
+        }
+    }
+
+    /// Call [`Self::try_run`], then send its error or the run it completed, if any.
+    fn run_and_notify(
+        &mut self,
+        entity: Option<Entity>,
+        commands: &mut Commands,
+        errors: &mut EventWriter<ComputeWorkerError<W>>,
+        finished: &mut EventWriter<ComputeWorkerFinished<W>>,
+    ) {
+        let last_run = self.last_run;
+        if let Err(err) = self.try_run() {
+            errors.send(err.with_entity(entity));
+        }
+
+        if let Some(run) = self.last_run.filter(|_| self.last_run != last_run) {
+            finished.send(ComputeWorkerFinished::new(run, entity));
+            for system in &self.on_finished_systems {
+                commands.run_system(*system);
+            }
+        }
+    }
+
+    /// Submit a run if one is due, and check if the runs in flight are done.
+    pub(crate) fn try_run(&mut self) -> WorkerResult<W> {
+        if self.failed() {
+            return Ok(());
+        }
+
+        if self.ready() {
+            self.state = WorkerState::Available;
+        }
+
+        if self.ready_to_execute() {
+            self.try_start_run()?;
+        }
+
+        // With a single frame in flight, wait for the GPU at the end of every run
+        match self.poll(self.frames_in_flight == 1) {
+            Ok(true) => self.state = WorkerState::FinishedWorking,
+            Ok(false) => {}
+            Err(err) => return Err(self.fail(err.into())),
+        }
+        Ok(())
+    }
+
+    /// Record and submit a run, unless its pipelines aren't ready yet.
+    /// Nothing is recorded if every staging slot is still in use.
+    pub(crate) fn try_start_run(&mut self) -> WorkerResult<W> {
+        // Steps are recorded as they go, wait before recording any of them
+        if pipelines_pending(&self.steps, &self.pipelines) || self.images_pending() {
+            return Ok(());
+        }
+        let Some(slot) = self.free_slots.pop() else {
+            return Err(Error::NoFreeStagingSlot.into());
+        };
+
+        if let Some(timings) = &mut self.timings {
+            timings.start_run(self.runs);
+        }
+
+        // The swaps of a failed run are rolled back, its commands are dropped on reset
+        let buffers = self.buffers.clone();
+        let buffer_lens = self.buffer_lens.clone();
+
+        // Workaround for interior mutability
+        let steps = std::mem::take(&mut self.steps);
+        let result = steps
+            .iter()
+            .enumerate()
+            .try_for_each(|(index, step)| self.run_step(step).map_err(|err| err.with_step(index)))
+            .and_then(|()| {
+                self.read_staging_buffers(slot)
+                    .map_err(ComputeWorkerError::from)
+            });
+        self.steps = steps;
+
+        let names = match result {
+            Ok(names) => names,
+            Err(err) => {
+                self.buffers = buffers;
+                self.buffer_lens = buffer_lens;
+                self.free_slots.push(slot);
+                return Err(self.fail(err));
+            }
+        };
+        self.submit();
+        if let Some(timings) = &mut self.timings {
+            timings.submitted(&self.render_queue, slot);
+        }
+        self.map_staging_buffers(slot, &names);
+        self.runs += 1;
+
+        match self.run_mode {
+            RunMode::Continuous => {}
+            RunMode::OneShot(_) => self.run_mode = RunMode::OneShot(false),
+        };
+        Ok(())
+    }
+
+    /// Block until the runs in flight are done, or fail once `timeout` has elapsed.
+    pub(crate) fn wait(&mut self, timeout: Option<Duration>) -> WorkerResult<W> {
+        let start = Instant::now();
+        while !self.in_flight.is_empty() {
+            match self.poll(timeout.is_none()) {
+                Ok(true) => self.state = WorkerState::FinishedWorking,
+                Ok(false) => {}
+                Err(err) => return Err(self.fail(err.into())),
+            }
+
+            if let Some(timeout) = timeout.filter(|timeout| start.elapsed() >= *timeout) {
+                if !self.in_flight.is_empty() {
+                    return Err(self.fail(Error::Timeout(timeout).into()));
+                }
+            }
+            std::thread::yield_now();
+        }
+        Ok(())
+    }
+
+    /// The diagnostics timings are published to: one per shader, and one for whole runs.
+    pub(crate) fn timing_diagnostics(&self) -> Vec<Diagnostic> {
+        if self.timings.is_none() {
+            return vec![];
+        }
+
+        let mut shaders = HashMap::<Uuid, &str>::default();
+        let _ = try_for_each_pass(&self.steps, &mut |compute_pass| {
+            shaders.insert(compute_pass.shader_uuid, compute_pass.shader_name);
+            Ok(())
+        });
+
+        let run = timings::diagnostic(
+            timings::diagnostic_id::<W>(Uuid::nil()),
+            std::any::type_name::<W>(),
+        );
+        let passes = shaders.into_iter().map(|(uuid, shader_name)| {
+            timings::diagnostic(timings::diagnostic_id::<W>(uuid), shader_name)
+        });
+        std::iter::once(run).chain(passes).collect()
+    }
+
+    pub(crate) fn publish_timings(mut worker: ResMut<Self>, mut diagnostics: Diagnostics) {
+        let Some(timings) = &mut worker.timings else {
+            return;
+        };
+
+        let (passes, run) = timings.take();
+        if let Some(run) = run {
+            diagnostics.add_measurement(timings::diagnostic_id::<W>(Uuid::nil()), || run);
+        }
+        for (uuid, duration) in passes {
+            diagnostics.add_measurement(timings::diagnostic_id::<W>(uuid), || duration);
+        }
+    }
+
+    #[inline]
+    /// Fulfill the pending readbacks of the buffers mapped in `slot`.
+    fn fulfill_readbacks(&mut self, slot: usize) {
+        for (name, pending) in std::mem::take(&mut self.pending_readbacks) {
+            let staging_buffer = &self.staging_buffers[&name];
+            if staging_buffer.mapped[slot] {
+                pending.fulfill(&staging_buffer.slots[slot].slice(..).get_mapped_range());
+            } else {
+                // This run didn't read it, keep waiting for the next one
+                self.pending_readbacks.push((name, pending));
+            }
+        }
+    }
+
+    fn unmap_slot(&mut self, slot: usize) {
+        for (name, staging_buffer) in self.staging_buffers.iter_mut() {
+            if staging_buffer.mapped[slot] {
+                staging_buffer.slots[slot].unmap();
+                staging_buffer.mapped[slot] = false;
+                staging_buffer.reallocate_slot(&self.render_device, name, slot);
+            }
+        }
+        self.free_slots.push(slot);
+    }
+
+    pub(crate) fn unmap_all(mut worker: ResMut<Self>) {
+        worker.unmap();
+    }
+
+    pub(crate) fn unmap_components(mut workers: Query<&mut Self>) {
+        for mut worker in &mut workers {
+            worker.unmap();
+        }
+    }
+
+    /// Unmap the staging buffers of the last completed run, so that their slot can be reused.
+    ///
+    /// They stay mapped with [`AppComputeWorkerBuilder::keep_last_results`],
+    /// until the next run completes.
+    pub(crate) fn unmap(&mut self) {
+        if self.keep_last_results {
+            return;
+        }
+        if let Some(slot) = self.mapped_slot.take() {
+            self.unmap_slot(slot);
+        }
+    }
+
+    /// Whether an image bound with [`AppComputeWorkerBuilder::add_texture_from_image`] isn't loaded yet.
+    #[inline]
+    fn images_pending(&self) -> bool {
+        self.images
+            .keys()
+            .any(|name| !self.textures.contains_key(name))
+    }
+
+    /// Recreate the textures of the images that were loaded or modified.
+    pub(crate) fn update_images(
+        &mut self,
+        images: &Assets<Image>,
+        changed: &HashSet<AssetId<Image>>,
+    ) {
+        for (name, handle) in &self.images {
+            if !changed.contains(&handle.id()) {
+                continue;
+            }
+            let Some(image) = images.get(handle) else {
+                continue;
+            };
+
+            let texture =
+                WorkerTexture::from_image(&self.render_device, &self.render_queue, name, image);
+            if let Some(old) = self.textures.insert(name.clone(), texture) {
+                let old = ResourceId::TextureView(old.view.id());
+                self.bind_groups
+                    .retain(|(_, _, resources), _| resources.iter().all(|(_, id)| *id != old));
+            }
+        }
+    }
+
+    pub(crate) fn extract_images(
+        mut worker: ResMut<Self>,
+        images: Res<Assets<Image>>,
+        mut events: EventReader<AssetEvent<Image>>,
+    ) {
+        let changed = changed_images(&mut events);
+        if !changed.is_empty() {
+            worker.update_images(&images, &changed);
+        }
+    }
+
+    pub(crate) fn extract_component_images(
+        mut workers: Query<&mut Self>,
+        images: Res<Assets<Image>>,
+        mut events: EventReader<AssetEvent<Image>>,
+    ) {
+        let changed = changed_images(&mut events);
+        if !changed.is_empty() {
+            for mut worker in &mut workers {
+                worker.update_images(&images, &changed);
+            }
+        }
+    }
+
+    pub(crate) fn extract_pipelines(
+        mut worker: ResMut<Self>,
+        pipeline_cache: Res<AppPipelineCache>,
+        mut errors: EventWriter<ComputeWorkerError<W>>,
+    ) {
+        if let Err(err) = worker.update_pipelines(&pipeline_cache) {
+            errors.send(err);
+        }
+    }
+
+    pub(crate) fn extract_component_pipelines(
+        mut workers: Query<(Entity, &mut Self)>,
+        pipeline_cache: Res<AppPipelineCache>,
+        mut errors: EventWriter<ComputeWorkerError<W>>,
+    ) {
+        for (entity, mut worker) in &mut workers {
+            if let Err(err) = worker.update_pipelines(&pipeline_cache) {
+                errors.send(err.with_entity(Some(entity)));
+            }
+        }
+    }
+
+    /// Take the pipelines that became ready or were recompiled in `pipeline_cache`,
+    /// and check the passes using them against their bindings.
+    pub(crate) fn update_pipelines(
+        &mut self,
+        pipeline_cache: &AppPipelineCache,
+    ) -> WorkerResult<W> {
+        if self.failed() {
+            return Ok(());
+        }
+        self.queue_specialized_pipelines(pipeline_cache);
+
+        for (key, cached_id) in &self.cached_pipeline_ids.clone() {
+            let Some(current) = self.pipelines.get(key) else {
+                continue;
+            };
+
+            let cached_id = *cached_id;
+            let generation = pipeline_cache.get_compute_pipeline_generation(cached_id);
+            // A pipeline already in use is swapped once it has been recompiled
+            let reloading = current.is_some();
+            if reloading && self.pipeline_generations.get(key) == Some(&generation) {
+                continue;
+            }
+
+            let Some(pipeline) = pipeline_cache.get_compute_pipeline(cached_id) else {
+                // Pipelines waiting for a shader to load are retried, the others failed for good
+                if !pipeline_cache.is_compute_pipeline_waiting(cached_id) {
+                    if let Some(reason) = pipeline_cache.get_compute_pipeline_error(cached_id) {
+                        let err = Error::PipelineFailed(
+                            self.shader_name(key.shader_uuid)
+                                .unwrap_or_default()
+                                .to_owned(),
+                            reason,
+                        );
+                        return Err(self.reload_failed(key, generation, err, None));
+                    }
+                }
+                continue;
+            };
+
+            // The passes are resolved again for the new pipeline, restored if it is invalid
+            let steps = reloading.then(|| self.steps.clone());
+
+            let bindings = pipeline_cache.get_bindings(cached_id);
+            if let Err(err) = self.resolve_bindings(key, bindings.as_deref()) {
+                return Err(self.reload_failed(key, generation, err, steps));
+            }
+
+            // Shaders that can't be reflected are left to wgpu's validation
+            if let Some(bindings) = bindings {
+                if let Err(err) = self.validate_bindings(key, &bindings) {
+                    return Err(self.reload_failed(key, generation, err, steps));
+                }
+            }
+
+            if let Some(workgroup_size) = pipeline_cache.get_workgroup_size(cached_id) {
+                self.workgroup_sizes.insert(key.clone(), workgroup_size);
+            }
+            if let Some(Some(previous)) = self.pipelines.get(key) {
+                // Their layouts come from the previous pipeline
+                let previous = previous.id();
+                self.bind_groups
+                    .retain(|(pipeline, _, _), _| *pipeline != previous);
+            }
+
+            // Only once its passes are known to be valid, so that they can't run otherwise
+            self.pipelines.insert(key.clone(), Some(pipeline.clone()));
+            self.pipeline_generations.insert(key.clone(), generation);
+        }
+        Ok(())
+    }
+
+    /// Report that the pipeline `key` failed to compile or is invalid.
+    ///
+    /// If the shader was reloaded, the worker keeps running its last valid pipeline with `steps`,
+    /// otherwise it fails.
+    fn reload_failed(
+        &mut self,
+        key: &PipelineKey,
+        generation: u64,
+        error: Error,
+        steps: Option<Vec<Step>>,
+    ) -> ComputeWorkerError<W> {
+        if !self.pipelines.get(key).is_some_and(Option::is_some) {
+            return self.fail_in_shader(key.shader_uuid, error);
+        }
+
+        if let Some(steps) = steps {
+            self.steps = steps;
+        }
+        // Reported once, until the shader changes again
+        self.pipeline_generations.insert(key.clone(), generation);
+        self.error_in_shader(key.shader_uuid, error)
+    }
+
+    /// Check that every pipeline of the worker is ready,
+    /// with the reason it failed to compile when `pipeline_cache` knows it.
+    pub(crate) fn check_pipelines(&mut self, pipeline_cache: &AppPipelineCache) -> WorkerResult<W> {
+        for (key, cached_id) in &self.cached_pipeline_ids.clone() {
+            // A reloaded shader that failed to compile keeps its last pipeline
+            if self.pipelines.get(key).is_some_and(Option::is_some) {
+                continue;
+            }
+            if let Some(reason) = pipeline_cache.get_compute_pipeline_error(*cached_id) {
+                return Err(self.fail_in_shader(
+                    key.shader_uuid,
+                    Error::PipelineFailed(
+                        self.shader_name(key.shader_uuid)
+                            .unwrap_or_default()
+                            .to_owned(),
+                        reason,
+                    ),
+                ));
+            }
+            return Err(Error::PipelineNotReady.into());
+        }
+        Ok(())
+    }
+
+    /// Move to [`WorkerState::Failed`] because of `error` in the passes of shader `uuid`.
+    fn fail_in_shader(&mut self, uuid: Uuid, error: Error) -> ComputeWorkerError<W> {
+        let err = self.error_in_shader(uuid, error);
+        self.fail(err)
+    }
+
+    /// Error in the passes of shader `uuid`, without failing the worker.
+    fn error_in_shader(&self, uuid: Uuid, error: Error) -> ComputeWorkerError<W> {
+        let mut err = ComputeWorkerError::from(error);
+        if let Some(shader_name) = self.shader_name(uuid) {
+            err = err.with_shader(shader_name);
+        }
+        err
+    }
+
+    /// Name of the shader `uuid` of one of the passes.
+    fn shader_name(&self, uuid: Uuid) -> Option<&'static str> {
+        let mut shader_name = None;
+        let _ = try_for_each_pass(&self.steps, &mut |compute_pass| {
+            if compute_pass.shader_uuid == uuid {
+                shader_name = Some(compute_pass.shader_name);
+            }
+            Ok(())
+        });
+        shader_name
+    }
+
+    /// Bind the buffers of the passes of pipeline `key` added with
+    /// [`AppComputeWorkerBuilder::bind_by_name`], now that its bindings are known.
+    fn resolve_bindings(
+        &mut self,
+        key: &PipelineKey,
+        reflected: Option<&[ReflectedBinding]>,
+    ) -> Result<()> {
+        try_for_each_pass_mut(&mut self.steps, &mut |compute_pass| {
+            if !compute_pass.uses(key) {
+                return Ok(());
+            }
+            let Some(vars) = &compute_pass.named else {
+                return Ok(());
+            };
+
+            let Some(reflected) = reflected else {
+                return Err(Error::ReflectionUnavailable(
+                    compute_pass.shader_name.to_owned(),
+                ));
+            };
+            compute_pass.groups =
+                resolve_named_bindings(compute_pass.shader_name, vars, reflected)?;
+            Ok(())
+        })
+    }
+
+    /// Check the resources bound to the passes of pipeline `key` against its bindings.
+    fn validate_bindings(&self, key: &PipelineKey, reflected: &[ReflectedBinding]) -> Result<()> {
+        let resource = |name: &str| {
+            if self.textures.contains_key(name) || self.samplers.contains_key(name) {
+                return Some(BoundResource::Handle);
+            }
+            let buffer = self.buffers.get(name)?;
+            Some(BoundResource::Buffer {
+                uniform: buffer.usage().contains(BufferUsages::UNIFORM),
+                read_only: self.read_only_buffers.contains(name),
+                size: buffer.size(),
+            })
+        };
+
+        try_for_each_pass(
+            &self.steps,
+            &mut |compute_pass| match compute_pass.uses(key) {
+                true => validate_bindings(compute_pass, reflected, resource),
+                false => Ok(()),
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{prelude::World, reflect::TypeUuid, render::render_resource::ShaderRef};
+    use wgpu::{Extent3d, TextureDimension, TextureFormat};
+
+    use super::*;
+    use crate::compute::{
+        harness::{load_wgsl, shader_handle, ComputeHarness},
+        shared::SharedComputeBuffers,
+        traits::ComputeShader,
+        worker_builder::AppComputeWorkerBuilder,
+    };
+
+    /// A pass binding `vars` in order to `@group(0)`.
+    fn pass(id: usize, dispatch: Dispatch, vars: &[&str]) -> ComputePass {
+        let bindings = vars
+            .iter()
+            .enumerate()
+            .map(|(binding, var)| Binding {
+                binding: binding as u32,
+                resource: var.to_string(),
+            })
+            .collect();
+
+        ComputePass {
+            id,
+            dispatch,
+            groups: vec![bindings],
+            named: None,
+            push_constants: vec![],
+            push_constant_ranges: vec![],
+            shader_uuid: Uuid::nil(),
+            shader_name: "shader",
+            shader_defs: vec![],
+            entry_point: Cow::Borrowed("main"),
+        }
+    }
+
+    #[test]
+    fn test_checked_byte_range() {
+        assert_eq!(checked_byte_range("a", 0..16, 16).unwrap(), 0..16);
+        assert_eq!(checked_byte_range("a", 8..8, 8).unwrap(), 8..8);
+        assert!(matches!(
+            checked_byte_range("a", 8..24, 16),
+            Err(Error::BufferOutOfBounds(_, range, 16)) if range == (8..24)
+        ));
+        assert!(checked_byte_range("a", u64::MAX..u64::MAX, 16).is_err());
+        let reversed = Range { start: 8, end: 4 };
+        assert!(matches!(
+            checked_byte_range("a", reversed.clone(), 16),
+            Err(Error::BufferOutOfBounds(_, range, 16)) if range == reversed
+        ));
+
+        assert_eq!(element_bytes(3, 4), 12);
+        let offset = element_bytes(usize::MAX / 2 + 1, 4);
+        assert!(matches!(
+            checked_byte_range("a", offset..offset, 16),
+            Err(Error::BufferOutOfBounds(..))
+        ));
+    }
+
+    #[test]
+    fn test_workgroups_for_elements() {
+        assert_eq!(workgroups_for_elements(10000, [32, 1, 1]), [313, 1, 1]);
+        assert_eq!(workgroups_for_elements(256, [256, 1, 1]), [1, 1, 1]);
+        assert_eq!(workgroups_for_elements(257, [256, 1, 1]), [2, 1, 1]);
+        assert_eq!(workgroups_for_elements(0, [64, 1, 1]), [0, 1, 1]);
+    }
+
+    #[test]
+    fn test_should_read() {
+        let staging_buffer = |policy| StagingBuffer {
+            slots: vec![],
+            mapped: vec![],
+            policy,
+            requested: false,
+            size: 0,
+        };
+
+        let mut every_two = staging_buffer(ReadbackPolicy::EveryNRuns(2));
+        assert!(every_two.should_read(0));
+        assert!(!every_two.should_read(1));
+        every_two.requested = true;
+        assert!(every_two.should_read(1));
+        assert!(!every_two.should_read(3));
+
+        for policy in [ReadbackPolicy::OnRequest, ReadbackPolicy::Never] {
+            let mut staging_buffer = staging_buffer(policy);
+            assert!(!staging_buffer.should_read(0));
+            staging_buffer.requested = true;
+            assert!(staging_buffer.should_read(1));
+            assert!(!staging_buffer.should_read(2));
+        }
+    }
+
+    #[test]
+    fn test_for_each_pass() {
+        let step = |id| Step::ComputePass(pass(id, Dispatch::Elements(0), &[]));
+        let mut steps = vec![
+            step(0),
+            Step::Repeat {
+                count: 4,
+                steps: vec![step(1), Step::Clear("a".to_owned()), step(2)],
+            },
+            Step::If {
+                flag: "flag".to_owned(),
+                steps: vec![Step::Swap("a".to_owned(), "b".to_owned()), step(3)],
+            },
+        ];
+
+        let mut ids = vec![];
+        for_each_pass_mut(&mut steps, |compute_pass| {
+            ids.push(compute_pass.id);
+            compute_pass.dispatch = Dispatch::Elements(8);
+        });
+        assert_eq!(ids, [0, 1, 2, 3]);
+
+        let mut count = 0;
+        try_for_each_pass(&steps, &mut |compute_pass| {
+            assert!(matches!(compute_pass.dispatch, Dispatch::Elements(8)));
+            count += 1;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(count, 4);
+    }
+
+    #[test]
+    fn test_resize_dispatches() {
+        let mut steps = vec![
+            Step::ComputePass(pass(0, Dispatch::Elements(7), &["a"])),
+            Step::ComputePass(pass(1, Dispatch::Elements(7), &["b"])),
+            Step::ComputePass(pass(2, Dispatch::Elements(3), &["a", "b"])),
+            Step::ComputePass(pass(3, Dispatch::Direct([7, 1, 1]), &["a"])),
+        ];
+        resize_dispatches(&mut steps, "a", 7, 9);
+
+        let mut dispatches = vec![];
+        try_for_each_pass(&steps, &mut |compute_pass| {
+            dispatches.push(match compute_pass.dispatch {
+                Dispatch::Elements(count) => count,
+                _ => 0,
+            });
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(dispatches, [9, 7, 3, 0]);
+    }
+
+    #[test]
+    fn test_pipelines_pending() {
+        let mut other = pass(1, Dispatch::Direct([1, 1, 1]), &[]);
+        other.entry_point = Cow::Borrowed("other");
+        let steps = vec![
+            Step::ComputePass(pass(0, Dispatch::Direct([1, 1, 1]), &[])),
+            Step::Repeat {
+                count: 2,
+                steps: vec![
+                    Step::Swap("a".to_owned(), "b".to_owned()),
+                    Step::ComputePass(other.clone()),
+                ],
+            },
+        ];
+
+        let mut pipelines = HashMap::default();
+        assert!(!pipelines_pending(&steps, &pipelines));
+        pipelines.insert(other.pipeline_key(), None);
+        assert!(pipelines_pending(&steps, &pipelines));
+    }
+
+    #[test]
+    fn test_max_dispatches() {
+        let step = Step::ComputePass(pass(0, Dispatch::Direct([1, 1, 1]), &[]));
+        let steps = vec![
+            step.clone(),
+            Step::Repeat {
+                count: 3,
+                steps: vec![
+                    step.clone(),
+                    Step::If {
+                        flag: "flag".to_owned(),
+                        steps: vec![step.clone(), step],
+                    },
+                ],
+            },
+            Step::Clear("a".to_owned()),
+        ];
+        assert_eq!(max_dispatches(&steps), 10);
+        assert_eq!(max_dispatches(&[]), 0);
+    }
+
+    #[test]
+    fn test_resolve_named_bindings() {
+        let reflected = |name: &str, group, binding| ReflectedBinding {
+            name: name.to_owned(),
+            group,
+            binding,
+            space: ReflectedSpace::Storage { read_only: true },
+            min_size: 4,
+        };
+        let reflected = [
+            reflected("params", 0, 0),
+            reflected("density", 1, 2),
+            reflected("particles_src", 1, 0),
+        ];
+        let vars = ["particles_src", "density", "params"].map(String::from);
+
+        let groups = resolve_named_bindings("shader", &vars, &reflected).unwrap();
+        let binding = |binding, buffer: &str| Binding {
+            binding,
+            resource: buffer.to_owned(),
+        };
+        assert_eq!(
+            groups,
+            vec![
+                vec![binding(0, "params")],
+                vec![binding(0, "particles_src"), binding(2, "density")],
+            ]
+        );
+
+        assert!(matches!(
+            resolve_named_bindings("shader", &["positions".to_owned()], &reflected),
+            Err(Error::BindingNotFound(_, name)) if name == "positions"
+        ));
+    }
+
+    #[test]
+    fn test_validate_bindings() {
+        let reflected = [
+            ReflectedBinding {
+                name: "params".to_owned(),
+                group: 0,
+                binding: 0,
+                space: ReflectedSpace::Uniform,
+                min_size: 16,
+            },
+            ReflectedBinding {
+                name: "density".to_owned(),
+                group: 0,
+                binding: 1,
+                space: ReflectedSpace::Storage { read_only: false },
+                min_size: 8,
+            },
+        ];
+        let resource = |name: &str| match name {
+            "params" => Some(BoundResource::Buffer {
+                uniform: true,
+                read_only: true,
+                size: 16,
+            }),
+            "density" => Some(BoundResource::Buffer {
+                uniform: false,
+                read_only: false,
+                size: 800,
+            }),
+            "positions" => Some(BoundResource::Buffer {
+                uniform: false,
+                read_only: true,
+                size: 800,
+            }),
+            "small" => Some(BoundResource::Buffer {
+                uniform: false,
+                read_only: false,
+                size: 4,
+            }),
+            "heatmap" => Some(BoundResource::Handle),
+            _ => None,
+        };
+        let mismatch = |vars: &[&str]| match validate_bindings(
+            &pass(0, Dispatch::Direct([1, 1, 1]), vars),
+            &reflected,
+            resource,
+        ) {
+            Err(Error::InvalidBinding { mismatch, .. }) => Some(*mismatch),
+            Err(err) => unreachable!("{err}"),
+            Ok(()) => None,
+        };
+
+        assert!(mismatch(&["params", "density"]).is_none());
+        assert!(matches!(
+            mismatch(&["params"]),
+            Some(BindingMismatch::Missing)
+        ));
+        assert!(matches!(
+            mismatch(&["params", "density", "density"]),
+            Some(BindingMismatch::Unused)
+        ));
+        assert!(matches!(
+            mismatch(&["density", "params"]),
+            Some(BindingMismatch::AddressSpace {
+                found: "storage buffer",
+                ..
+            })
+        ));
+        assert!(matches!(
+            mismatch(&["params", "heatmap"]),
+            Some(BindingMismatch::AddressSpace {
+                found: "texture or sampler",
+                ..
+            })
+        ));
+        assert!(matches!(
+            mismatch(&["params", "positions"]),
+            Some(BindingMismatch::ReadOnly)
+        ));
+        assert!(matches!(
+            mismatch(&["params", "small"]),
+            Some(BindingMismatch::TooSmall {
+                size: 4,
+                min_size: 8
+            })
+        ));
+    }
+
+    /// A worker with a single buffer, whatever `N`.
+    struct ValuesWorker<const N: usize>;
+
+    impl<const N: usize> ComputeWorker for ValuesWorker<N> {
+        fn build(world: &mut World) -> AppComputeWorker<Self> {
+            let mut builder = AppComputeWorkerBuilder::new(world);
+            builder.add_staging("values", &vec![1u32, 2, 3, 4]);
+            builder.build()
+        }
+    }
+
+    #[test]
+    fn test_restore_from_other_worker() {
+        let snapshot = {
+            let mut harness = ComputeHarness::<ValuesWorker<0>>::new(|_| {});
+            let snapshot = harness.build().worker_mut().snapshot();
+            assert!(harness.worker_mut().try_restore(&snapshot).is_ok());
+            snapshot
+        };
+
+        // Same buffers, but another worker
+        let mut other = ComputeHarness::<ValuesWorker<1>>::new(|_| {});
+        assert!(matches!(
+            other.build().worker_mut().try_restore(&snapshot),
+            Err(Error::InvalidSnapshot(_))
+        ));
+    }
+
+    #[test]
+    fn test_restore_overwrites_buffers() {
+        let mut harness = ComputeHarness::<ValuesWorker<6>>::new(|_| {});
+        let values = BufferHandle::<Vec<u32>>::from_name("values");
+        let snapshot = harness.build().worker_mut().snapshot();
+
+        harness.worker_mut().write_slice(&values, &[5, 6, 7, 8]);
+        harness.run(1);
+        assert_eq!(harness.read_vec(&values), [5, 6, 7, 8]);
+
+        harness.worker_mut().restore(&snapshot);
+        harness.run(1);
+        assert_eq!(harness.read_vec(&values), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_write_at_and_read_range() {
+        let mut harness = ComputeHarness::<ValuesWorker<5>>::new(|_| {});
+        let values = BufferHandle::<Vec<u32>>::from_name("values");
+
+        let mut worker = harness.build().worker_mut();
+        worker.write_at(&values, 2, &[7, 8]);
+        assert!(matches!(
+            worker.try_write_at(&values, 3, &[9, 10]),
+            Err(Error::BufferOutOfBounds(..))
+        ));
+        harness.run(1);
+
+        let worker = harness.worker();
+        assert_eq!(worker.read_range(&values, 1..3)[..], [2, 7]);
+        assert!(matches!(
+            worker.try_read_range(&values, 2..5),
+            Err(Error::BufferOutOfBounds(..))
+        ));
+        assert!(matches!(
+            worker.try_read_range(&values, Range { start: 3, end: 1 }),
+            Err(Error::BufferOutOfBounds(..))
+        ));
+    }
+
+    #[test]
+    fn test_readback_on_request() {
+        let mut harness = ComputeHarness::<ValuesWorker<4>>::new(|_| {});
+        let values = BufferHandle::<Vec<u32>>::from_name("values");
+        harness
+            .build()
+            .worker_mut()
+            .set_readback_policy(&values, ReadbackPolicy::OnRequest);
+
+        harness.run(1);
+        assert!(matches!(
+            harness.worker().try_read_slice(&values),
+            Err(Error::StagingBufferNotMapped(_))
+        ));
+
+        harness.worker_mut().request_readback(&values);
+        harness.run(1);
+        assert_eq!(harness.read_vec(&values), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_read_async() {
+        let mut harness = ComputeHarness::<ValuesWorker<3>>::new(|_| {});
+        let values = BufferHandle::<Vec<u32>>::from_name("values");
+
+        let mut readback = harness.build().worker_mut().read_async(&values);
+        assert!(!readback.is_ready());
+        assert_eq!(readback.try_take(), None);
+        harness.run(1);
+        assert!(readback.is_ready());
+        assert_eq!(readback.try_take(), Some(vec![1, 2, 3, 4]));
+
+        // Fulfilled by the next run only
+        let readback = harness.worker_mut().read_async(&values);
+        harness.worker_mut().write_slice(&values, &[5, 6, 7, 8]);
+        harness.run(1);
+        assert_eq!(bevy::tasks::block_on(readback), [5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn test_snapshot_flushes_pending_commands() {
+        let mut harness = ComputeHarness::<ValuesWorker<2>>::new(|_| {});
+        let values = BufferHandle::<Vec<u32>>::from_name("values");
+        let mut worker = harness.build().worker_mut();
+
+        // The copy preserving the contents is only recorded until the next run
+        worker.resize(&values, 6, true);
+        let snapshot = worker.snapshot();
+        assert_eq!(
+            snapshot.buffer("values"),
+            Some(cast_slice(&[1u32, 2, 3, 4, 0, 0]))
+        );
+        assert!(worker.try_restore(&snapshot).is_ok());
+    }
+
+    struct TextureWorker;
+
+    impl ComputeWorker for TextureWorker {
+        fn build(world: &mut World) -> AppComputeWorker<Self> {
+            let mut builder = AppComputeWorkerBuilder::new(world);
+            builder.add_staging("values", &vec![1u32, 2, 3, 4]);
+            builder.add_storage_texture(
+                "image",
+                Extent3d {
+                    width: 4,
+                    height: 4,
+                    depth_or_array_layers: 1,
+                },
+                TextureDimension::D2,
+                TextureFormat::R32Float,
+            );
+            builder.build()
+        }
+    }
+
+    #[test]
+    fn test_snapshot_with_textures() {
+        let mut harness = ComputeHarness::<TextureWorker>::new(|_| {});
+        assert!(matches!(
+            harness.build().worker_mut().try_snapshot(),
+            Err(Error::InvalidSnapshot(_))
+        ));
+    }
+
+    const INCREMENT_SHADER: &str = r"
+        @group(0) @binding(0) var<storage, read_write> values: array<u32>;
+
+        @compute @workgroup_size(4)
+        fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+            values[id.x] += 1u;
+        }
+    ";
+
+    #[derive(TypeUuid)]
+    #[uuid = "e84b6c1d-27f3-4a95-b0d8-3c6f9a1e5b72"]
+    struct IncrementShader;
+
+    impl ComputeShader for IncrementShader {
+        fn shader() -> ShaderRef {
+            shader_handle::<Self>()
+        }
+    }
+
+    struct IncrementWorker;
+
+    impl ComputeWorker for IncrementWorker {
+        fn build(world: &mut World) -> AppComputeWorker<Self> {
+            let mut builder = AppComputeWorkerBuilder::new(world);
+            let a = builder.add_staging("a", &vec![1u32, 2, 3, 4]);
+            let b = builder.add_staging("b", &vec![5u32, 6, 7, 8]);
+            builder
+                .add_pass::<IncrementShader>([1, 1, 1], &[&a])
+                .add_pass::<IncrementShader>([1, 1, 1], &[&a])
+                .add_pass::<IncrementShader>([1, 1, 1], &[&b]);
+            builder.build()
+        }
+    }
+
+    #[test]
+    fn test_shared_bind_groups() {
+        let mut harness = ComputeHarness::<IncrementWorker>::new(|app| {
+            load_wgsl::<IncrementShader>(app, INCREMENT_SHADER)
+        });
+        harness.run(1);
+
+        let a = BufferHandle::<Vec<u32>>::from_name("a");
+        let b = BufferHandle::<Vec<u32>>::from_name("b");
+        assert_eq!(harness.read_vec(&a), [3, 4, 5, 6]);
+        assert_eq!(harness.read_vec(&b), [6, 7, 8, 9]);
+        // The passes binding `a` share its bind group
+        assert_eq!(harness.worker().bind_groups.len(), 2);
+    }
+
+    struct RepeatWorker;
+
+    impl ComputeWorker for RepeatWorker {
+        fn build(world: &mut World) -> AppComputeWorker<Self> {
+            let mut builder = AppComputeWorkerBuilder::new(world);
+            let a = builder.add_staging("a", &vec![1u32, 2, 3, 4]);
+            let b = builder.add_staging("b", &vec![5u32, 6, 7, 8]);
+            builder
+                .add_repeat(3, |builder| {
+                    builder.add_pass::<IncrementShader>([1, 1, 1], &[&a]);
+                })
+                .add_clear(&b);
+            builder.one_shot().build()
+        }
+    }
+
+    #[test]
+    fn test_repeat_and_clear() {
+        let mut harness = ComputeHarness::<RepeatWorker>::new(|app| {
+            load_wgsl::<IncrementShader>(app, INCREMENT_SHADER)
+        });
+        harness.run(1);
+
+        let a = BufferHandle::<Vec<u32>>::from_name("a");
+        let b = BufferHandle::<Vec<u32>>::from_name("b");
+        assert_eq!(harness.read_vec(&a), [4, 5, 6, 7]);
+        assert_eq!(harness.read_vec(&b), [0; 4]);
+    }
+
+    struct ElementsWorker;
+
+    impl ComputeWorker for ElementsWorker {
+        fn build(world: &mut World) -> AppComputeWorker<Self> {
+            let mut builder = AppComputeWorkerBuilder::new(world);
+            let values = builder.add_staging("values", &vec![0u32; 8]);
+            builder.add_pass_for_elements::<IncrementShader>(8, &[&values]);
+            builder.build()
+        }
+    }
+
+    #[test]
+    fn test_set_element_count() {
+        let mut harness = ComputeHarness::<ElementsWorker>::new(|app| {
+            load_wgsl::<IncrementShader>(app, INCREMENT_SHADER)
+        });
+        harness.run(1);
+
+        let values = BufferHandle::<Vec<u32>>::from_name("values");
+        assert_eq!(harness.read_vec(&values), [1; 8]);
+
+        // A single workgroup of 4 invocations
+        harness.worker_mut().set_element_count::<IncrementShader>(4);
+        harness.run(1);
+        assert_eq!(harness.read_vec(&values), [2, 2, 2, 2, 1, 1, 1, 1]);
+    }
+
+    struct ResizeWorker;
+
+    impl ComputeWorker for ResizeWorker {
+        fn build(world: &mut World) -> AppComputeWorker<Self> {
+            let mut builder = AppComputeWorkerBuilder::new(world);
+            builder.add_staging("values", &vec![1u32, 2, 3, 4]);
+            builder.write_shared::<Vec<u32>>("shared");
+            builder.build()
+        }
+    }
+
+    #[test]
+    fn test_resize() {
+        let mut shared = SharedComputeBuffers::default();
+        shared.add_storage("shared", &vec![0u32; 4]);
+        let mut harness = ComputeHarness::<ResizeWorker>::new(|_| {});
+        harness.insert_resource(shared).run(1);
+
+        let values = BufferHandle::<Vec<u32>>::from_name("values");
+        let huge = BufferHandle::<Vec<[u8; 1 << 30]>>::from_name("values");
+        let mut worker = harness.worker_mut();
+        assert!(matches!(
+            worker.try_resize(&values, 0, true),
+            Err(Error::EmptyResize(_))
+        ));
+        assert!(matches!(
+            worker.try_resize(&huge, u32::MAX as usize, false),
+            Err(Error::BufferOutOfBounds(..))
+        ));
+        assert!(matches!(
+            worker.try_resize(&BufferHandle::<Vec<u32>>::from_name("shared"), 8, false),
+            Err(Error::SharedBufferResize(_))
+        ));
+
+        // The last results keep their length until the next run
+        worker.resize(&values, 6, true);
+        assert_eq!(worker.read_slice(&values)[..], [1, 2, 3, 4]);
+        harness.run(1);
+        assert_eq!(harness.read_vec(&values), [1, 2, 3, 4, 0, 0]);
+    }
+
+    struct SwapWorker;
+
+    impl ComputeWorker for SwapWorker {
+        fn build(world: &mut World) -> AppComputeWorker<Self> {
+            let mut builder = AppComputeWorkerBuilder::new(world);
+            let a = builder.add_staging("a", &vec![1u32, 2]);
+            let b = builder.add_staging("b", &vec![3u32, 4]);
+            builder.add_swap(&a, &b).add_if("fail", |builder| {
+                builder.add_copy_range(&a, &b, 0..8);
+            });
+            builder.build()
+        }
+    }
+
+    #[test]
+    fn test_failed_run_rolls_back_swaps() {
+        let mut harness = ComputeHarness::<SwapWorker>::new(|_| {});
+        harness.build().worker_mut().set_flag("fail", true);
+        // The copy of the conditional step
+        assert!(matches!(
+            harness.try_run(1),
+            Err(ComputeWorkerError {
+                error: Error::BufferOutOfBounds(..),
+                step: Some(1),
+                shader: None,
+                ..
+            })
+        ));
+
+        let mut worker = harness.worker_mut();
+        worker.reset();
+        worker.set_flag("fail", false);
+        harness.run(1);
+
+        // Swapped once, by the run that succeeded
+        let a = BufferHandle::<Vec<u32>>::from_name("a");
+        assert_eq!(harness.read_vec(&a), [3, 4]);
+    }
+
+    struct TimedWorker;
+
+    impl ComputeWorker for TimedWorker {
+        fn build(world: &mut World) -> AppComputeWorker<Self> {
+            let mut builder = AppComputeWorkerBuilder::new(world);
+            builder.add_staging("values", &vec![1u32, 2]);
+            builder
+                .timings_with_policy(ReadbackPolicy::EveryNRuns(2))
+                .build()
+        }
+    }
+
+    #[test]
+    fn test_timings_policy() {
+        let mut harness = ComputeHarness::<TimedWorker>::new(|_| {});
+        let run_timed = |harness: &mut ComputeHarness<TimedWorker>| {
+            harness.run(1);
+            let mut worker = harness.worker_mut();
+            worker.timings.as_mut().unwrap().take().1.is_some()
+        };
+
+        assert!(run_timed(&mut harness));
+        assert!(!run_timed(&mut harness));
+        assert!(run_timed(&mut harness));
+        harness.worker_mut().request_timings();
+        assert!(run_timed(&mut harness));
+    }
 }
@@ -1,36 +1,86 @@
-use std::{borrow::Cow, marker::PhantomData};
+use std::{borrow::Cow, marker::PhantomData, mem::size_of, ops::Range, sync::Arc};
 
 use bevy::{
-    prelude::{AssetServer, World},
+    ecs::system::{IntoSystem, SystemId},
+    prelude::{AssetServer, Assets, Handle, World},
     render::{
         render_resource::{
             encase::{private::WriteInto, StorageBuffer, UniformBuffer},
-            Buffer, ComputePipelineDescriptor, ShaderRef, ShaderType,
+            Buffer, ComputePipelineDescriptor, Sampler, ShaderDefVal, ShaderRef, ShaderType,
         },
-        renderer::RenderDevice,
+        renderer::{RenderDevice, RenderQueue},
+        texture::Image,
     },
-    utils::{HashMap, Uuid},
+    utils::{HashMap, HashSet, Uuid},
+};
+use bytemuck::{bytes_of, NoUninit};
+use wgpu::{
+    util::BufferInitDescriptor, BufferDescriptor, BufferUsages, Extent3d, PushConstantRange,
+    SamplerDescriptor, ShaderStages, TextureDescriptor, TextureDimension, TextureFormat,
+    TextureUsages,
 };
-use wgpu::{util::BufferInitDescriptor, BufferDescriptor, BufferUsages};
 
 use super::{
-    pipeline_cache::{AppPipelineCache, CachedAppComputePipelineId},
+    buffer::{BufferHandle, DispatchIndirectArgs, PushConstantsHandle, ResourceHandle},
+    shared::SharedComputeBuffers,
+    texture::{SamplerHandle, TextureHandle, WorkerTexture},
     traits::{ComputeShader, ComputeWorker},
-    worker::{AppComputeWorker, ComputePass, RunMode, StagingBuffer, Step},
+    worker::{
+        compute_push_constants, AppComputeWorker, Binding, ComputePass, Dispatch, FinishedFn,
+        PipelineKey, ReadbackPolicy, RunMode, Step,
+    },
 };
 
 /// A builder struct to build [`AppComputeWorker<W>`]
 /// from your structs implementing [`ComputeWorker`]
 pub struct AppComputeWorkerBuilder<'a, W: ComputeWorker> {
     pub(crate) world: &'a mut World,
-    pub(crate) cached_pipeline_ids: HashMap<Uuid, CachedAppComputePipelineId>,
+    pub(crate) pipeline_descriptors: HashMap<Uuid, ComputePipelineDescriptor>,
     pub(crate) buffers: HashMap<String, Buffer>,
-    pub(crate) staging_buffers: HashMap<String, StagingBuffer>,
+    pub(crate) read_only_buffers: HashSet<String>,
+    /// Buffers of [`SharedComputeBuffers`] bound to this worker.
+    pub(crate) shared_buffers: HashSet<String>,
+    pub(crate) textures: HashMap<String, WorkerTexture>,
+    pub(crate) images: HashMap<String, Handle<Image>>,
+    pub(crate) samplers: HashMap<String, Sampler>,
+    pub(crate) staging_buffers: HashMap<String, ReadbackPolicy>,
     pub(crate) steps: Vec<Step>,
+    pub(crate) flags: HashMap<String, bool>,
+    /// Number of passes added so far, to give each one an id.
+    passes: usize,
     pub(crate) run_mode: RunMode,
+    pub(crate) frames_in_flight: usize,
+    pub(crate) timings: Option<ReadbackPolicy>,
+    pub(crate) keep_last_results: bool,
+    pub(crate) on_finished: Vec<Arc<FinishedFn<W>>>,
+    pub(crate) on_finished_systems: Vec<SystemId>,
     _phantom: PhantomData<W>,
 }
 
+/// The pipeline of a pass of `S`, running `entry_point` or [`ComputeShader::entry_point`].
+fn pipeline_key<S: ComputeShader>(
+    entry_point: Option<Cow<'static, str>>,
+    shader_defs: &[ShaderDefVal],
+) -> PipelineKey {
+    PipelineKey {
+        shader_uuid: S::TYPE_UUID,
+        entry_point: entry_point.unwrap_or(Cow::Borrowed(S::entry_point())),
+        shader_defs: shader_defs.to_vec(),
+        push_constant_ranges: S::push_constant_ranges().to_vec(),
+    }
+}
+
+/// Bind `vars` in order to `@binding(0..)`.
+fn positional_bindings(vars: &[&dyn ResourceHandle]) -> Vec<Binding> {
+    vars.iter()
+        .enumerate()
+        .map(|(binding, var)| Binding {
+            binding: binding as u32,
+            resource: var.name().to_owned(),
+        })
+        .collect()
+}
+
 impl<'a, W: ComputeWorker> AppComputeWorkerBuilder<'a, W> {
     /// Create a new builder.
     ///
@@ -38,17 +88,33 @@ impl<'a, W: ComputeWorker> AppComputeWorkerBuilder<'a, W> {
     pub fn new(world: &'a mut World) -> Self {
         Self {
             world,
-            cached_pipeline_ids: HashMap::default(),
+            pipeline_descriptors: HashMap::default(),
             buffers: HashMap::default(),
+            read_only_buffers: HashSet::default(),
+            shared_buffers: HashSet::default(),
+            textures: HashMap::default(),
+            images: HashMap::default(),
+            samplers: HashMap::default(),
             staging_buffers: HashMap::default(),
             steps: vec![],
+            flags: HashMap::default(),
+            passes: 0,
             run_mode: RunMode::Continuous,
+            frames_in_flight: 1,
+            timings: None,
+            keep_last_results: false,
+            on_finished: vec![],
+            on_finished_systems: vec![],
             _phantom: PhantomData,
         }
     }
 
     /// Add a new uniform buffer to the worker, and fill it with `uniform`.
-    pub fn add_uniform<T: ShaderType + WriteInto>(&mut self, name: &str, uniform: &T) -> &mut Self {
+    pub fn add_uniform<T: ShaderType + WriteInto>(
+        &mut self,
+        name: &str,
+        uniform: &T,
+    ) -> BufferHandle<T> {
         T::assert_uniform_compat();
         let mut buffer = UniformBuffer::new(Vec::new());
         buffer.write::<T>(uniform).unwrap();
@@ -60,14 +126,18 @@ impl<'a, W: ComputeWorker> AppComputeWorkerBuilder<'a, W> {
             render_device.create_buffer_with_data(&BufferInitDescriptor {
                 label: Some(name),
                 contents: buffer.as_ref(),
-                usage: BufferUsages::COPY_DST | BufferUsages::UNIFORM,
+                usage: BufferUsages::COPY_DST | BufferUsages::COPY_SRC | BufferUsages::UNIFORM,
             }),
         );
-        self
+        BufferHandle::from_name(name)
     }
 
     /// Add a new storage buffer to the worker, and fill it with `storage`. It will be read only.
-    pub fn add_storage<T: ShaderType + WriteInto>(&mut self, name: &str, storage: &T) -> &mut Self {
+    pub fn add_storage<T: ShaderType + WriteInto>(
+        &mut self,
+        name: &str,
+        storage: &T,
+    ) -> BufferHandle<T> {
         let mut buffer = StorageBuffer::new(Vec::new());
         buffer.write::<T>(storage).unwrap();
 
@@ -78,10 +148,11 @@ impl<'a, W: ComputeWorker> AppComputeWorkerBuilder<'a, W> {
             render_device.create_buffer_with_data(&BufferInitDescriptor {
                 label: Some(name),
                 contents: buffer.as_ref(),
-                usage: BufferUsages::COPY_DST | BufferUsages::STORAGE,
+                usage: BufferUsages::COPY_DST | BufferUsages::COPY_SRC | BufferUsages::STORAGE,
             }),
         );
-        self
+        self.read_only_buffers.insert(name.to_owned());
+        BufferHandle::from_name(name)
     }
 
     /// Add a new read/write storage buffer to the worker, and fill it with `storage`.
@@ -89,7 +160,7 @@ impl<'a, W: ComputeWorker> AppComputeWorkerBuilder<'a, W> {
         &mut self,
         name: &str,
         storage: &T,
-    ) -> &mut Self {
+    ) -> BufferHandle<T> {
         let mut buffer = StorageBuffer::new(Vec::new());
         buffer.write::<T>(storage).unwrap();
 
@@ -103,36 +174,64 @@ impl<'a, W: ComputeWorker> AppComputeWorkerBuilder<'a, W> {
                 usage: BufferUsages::COPY_DST | BufferUsages::COPY_SRC | BufferUsages::STORAGE,
             }),
         );
-        self
+        BufferHandle::from_name(name)
     }
 
-    /// Create two staging buffers, one to read from and one to write to.
-    /// Additionally, it will create a read/write storage buffer to access from
-    /// your shaders.
+    /// Create a read/write storage buffer to access from your shaders, and
+    /// staging buffers to read it back, one per frame in flight.
     /// The buffer will be filled with `data`
-    pub fn add_staging<T: ShaderType + WriteInto>(&mut self, name: &str, data: &T) -> &mut Self {
-        self.add_rw_storage(name, data);
-        let buffer = self.buffers.get(name).unwrap();
+    pub fn add_staging<T: ShaderType + WriteInto>(
+        &mut self,
+        name: &str,
+        data: &T,
+    ) -> BufferHandle<T> {
+        let handle = self.add_rw_storage(name, data);
+        self.staging_buffers
+            .insert(name.to_owned(), ReadbackPolicy::default());
+        handle
+    }
+
+    /// Set when `target` staging buffer is copied back to the CPU.
+    /// By default, staging buffers are read back after every run.
+    pub fn readback_policy<H: ResourceHandle + ?Sized>(
+        &mut self,
+        target: &H,
+        policy: ReadbackPolicy,
+    ) -> &mut Self {
+        let Some(current) = self.staging_buffers.get_mut(target.name()) else {
+            panic!("{} isn't a staging buffer", target.name());
+        };
+        *current = policy;
+        self
+    }
 
+    /// Add a new buffer holding the workgroup counts of an indirect pass, and fill it with `args`.
+    ///
+    /// It can be bound as a read/write storage buffer, so a previous pass can
+    /// size the dispatch of [`Self::add_pass_indirect`] without a round-trip to the CPU.
+    pub fn add_indirect_args(
+        &mut self,
+        name: &str,
+        args: &DispatchIndirectArgs,
+    ) -> BufferHandle<DispatchIndirectArgs> {
         let render_device = self.world.resource::<RenderDevice>();
 
-        let staging = StagingBuffer {
-            mapped: true,
-            buffer: render_device.create_buffer(&BufferDescriptor {
+        self.buffers.insert(
+            name.to_owned(),
+            render_device.create_buffer_with_data(&BufferInitDescriptor {
                 label: Some(name),
-                size: buffer.size(),
-                usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
-                mapped_at_creation: true,
+                contents: bytes_of(args),
+                usage: BufferUsages::COPY_DST
+                    | BufferUsages::COPY_SRC
+                    | BufferUsages::STORAGE
+                    | BufferUsages::INDIRECT,
             }),
-        };
-
-        self.staging_buffers.insert(name.to_owned(), staging);
-
-        self
+        );
+        BufferHandle::from_name(name)
     }
 
     /// Add a new empty uniform buffer to the worker.
-    pub fn add_empty_uniform(&mut self, name: &str, size: u64) -> &mut Self {
+    pub fn add_empty_uniform<T>(&mut self, name: &str, size: u64) -> BufferHandle<T> {
         let render_device = self.world.resource::<RenderDevice>();
 
         self.buffers.insert(
@@ -140,16 +239,16 @@ impl<'a, W: ComputeWorker> AppComputeWorkerBuilder<'a, W> {
             render_device.create_buffer(&BufferDescriptor {
                 label: Some(name),
                 size,
-                usage: BufferUsages::COPY_DST | BufferUsages::UNIFORM,
+                usage: BufferUsages::COPY_DST | BufferUsages::COPY_SRC | BufferUsages::UNIFORM,
                 mapped_at_creation: false,
             }),
         );
 
-        self
+        BufferHandle::from_name(name)
     }
 
     /// Add a new empty storage buffer to the worker. It will be read only.
-    pub fn add_empty_storage(&mut self, name: &str, size: u64) -> &mut Self {
+    pub fn add_empty_storage<T>(&mut self, name: &str, size: u64) -> BufferHandle<T> {
         let render_device = self.world.resource::<RenderDevice>();
 
         self.buffers.insert(
@@ -157,15 +256,16 @@ impl<'a, W: ComputeWorker> AppComputeWorkerBuilder<'a, W> {
             render_device.create_buffer(&BufferDescriptor {
                 label: Some(name),
                 size,
-                usage: BufferUsages::COPY_DST | BufferUsages::STORAGE,
+                usage: BufferUsages::COPY_DST | BufferUsages::COPY_SRC | BufferUsages::STORAGE,
                 mapped_at_creation: false,
             }),
         );
-        self
+        self.read_only_buffers.insert(name.to_owned());
+        BufferHandle::from_name(name)
     }
 
     /// Add a new empty read/write storage buffer to the worker.
-    pub fn add_empty_rw_storage(&mut self, name: &str, size: u64) -> &mut Self {
+    pub fn add_empty_rw_storage<T>(&mut self, name: &str, size: u64) -> BufferHandle<T> {
         let render_device = self.world.resource::<RenderDevice>();
 
         self.buffers.insert(
@@ -177,75 +277,477 @@ impl<'a, W: ComputeWorker> AppComputeWorkerBuilder<'a, W> {
                 mapped_at_creation: false,
             }),
         );
-        self
+        BufferHandle::from_name(name)
     }
 
-    /// Create two staging buffers, one to read from and one to write to.
-    /// Additionally, it will create a read/write storage buffer to access from
-    /// your shaders.
+    /// Create a read/write storage buffer to access from your shaders, and
+    /// staging buffers to read it back, one per frame in flight.
     /// The buffer will empty.
-    pub fn add_empty_staging(&mut self, name: &str, size: u64) -> &mut Self {
-        self.add_empty_rw_storage(name, size);
+    pub fn add_empty_staging<T>(&mut self, name: &str, size: u64) -> BufferHandle<T> {
+        let handle = self.add_empty_rw_storage(name, size);
+        self.staging_buffers
+            .insert(name.to_owned(), ReadbackPolicy::default());
+        handle
+    }
 
-        let buffer = self.buffers.get(name).unwrap();
+    /// Bind the buffer `name` of [`SharedComputeBuffers`] to this worker, to write to it.
+    ///
+    /// This worker will submit its runs before the workers reading from it.
+    pub fn write_shared<T>(&mut self, name: &str) -> BufferHandle<T> {
+        self.add_shared(name, true);
+        BufferHandle::from_name(name)
+    }
 
-        let render_device = self.world.resource::<RenderDevice>();
+    /// Bind the buffer `name` of [`SharedComputeBuffers`] to this worker, to read from it.
+    /// It will be read only.
+    ///
+    /// This worker will submit its runs after the workers writing to it.
+    pub fn read_shared<T>(&mut self, name: &str) -> BufferHandle<T> {
+        self.add_shared(name, false);
+        self.read_only_buffers.insert(name.to_owned());
+        BufferHandle::from_name(name)
+    }
 
-        let staging = StagingBuffer {
-            mapped: true,
-            buffer: render_device.create_buffer(&BufferDescriptor {
-                label: Some(name),
-                size: buffer.size(),
-                usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
-                mapped_at_creation: true,
-            }),
+    fn add_shared(&mut self, name: &str, write: bool) {
+        let render_device = self.world.resource::<RenderDevice>().clone();
+        let Some(mut shared) = self.world.get_resource_mut::<SharedComputeBuffers>() else {
+            panic!("Shared buffer {name} can't be used without `SharedComputeBuffers`");
         };
+        let Some(buffer) = shared.get(&render_device, name, std::any::type_name::<W>(), write)
+        else {
+            panic!("Shared buffer {name} wasn't declared in `SharedComputeBuffers`");
+        };
+        self.buffers.insert(name.to_owned(), buffer);
+        self.shared_buffers.insert(name.to_owned());
+    }
 
-        self.staging_buffers.insert(name.to_owned(), staging);
+    /// Add a new storage texture to the worker, bindable as a `texture_storage_*`
+    /// or a sampled `texture_*`. `size.depth_or_array_layers` is the depth of a 3D texture,
+    /// or the number of layers of a 2D texture array.
+    pub fn add_storage_texture(
+        &mut self,
+        name: &str,
+        size: Extent3d,
+        dimension: TextureDimension,
+        format: TextureFormat,
+    ) -> TextureHandle {
+        let render_device = self.world.resource::<RenderDevice>();
 
-        self
+        let texture = render_device.create_texture(&TextureDescriptor {
+            label: Some(name),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension,
+            format,
+            usage: TextureUsages::STORAGE_BINDING
+                | TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_SRC
+                | TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        self.textures
+            .insert(name.to_owned(), WorkerTexture::new(name, texture));
+        TextureHandle::from_name(name)
+    }
+
+    /// Create a storage texture to access from your shaders, and
+    /// staging buffers to read it back, one per frame in flight.
+    /// See [`AppComputeWorker::read_image`] to display it.
+    ///
+    /// Panics if texels of `format` can't be read back, e.g. for depth stencil formats.
+    pub fn add_staging_texture(
+        &mut self,
+        name: &str,
+        size: Extent3d,
+        dimension: TextureDimension,
+        format: TextureFormat,
+    ) -> TextureHandle {
+        let handle = self.add_storage_texture(name, size, dimension, format);
+        if let Err(err) = self.textures[name].staging_size() {
+            panic!("{err}");
+        }
+        self.staging_buffers
+            .insert(name.to_owned(), ReadbackPolicy::default());
+        handle
+    }
+
+    /// Add a new sampled texture to the worker, bound to the `image` asset.
+    /// It will be read only.
+    ///
+    /// The texture is recreated whenever the image changes, and the worker
+    /// waits for it to be loaded before running.
+    pub fn add_texture_from_image(&mut self, name: &str, image: &Handle<Image>) -> TextureHandle {
+        if let Some(loaded) = self
+            .world
+            .get_resource::<Assets<Image>>()
+            .and_then(|images| images.get(image))
+        {
+            let texture = WorkerTexture::from_image(
+                self.world.resource::<RenderDevice>(),
+                self.world.resource::<RenderQueue>(),
+                name,
+                loaded,
+            );
+            self.textures.insert(name.to_owned(), texture);
+        }
+
+        self.images.insert(name.to_owned(), image.clone());
+        TextureHandle::from_name(name)
+    }
+
+    /// Add a new sampler to the worker, to sample its textures with.
+    pub fn add_sampler(&mut self, name: &str, descriptor: &SamplerDescriptor) -> SamplerHandle {
+        let render_device = self.world.resource::<RenderDevice>();
+
+        let mut descriptor = descriptor.clone();
+        descriptor.label = Some(name);
+        self.samplers
+            .insert(name.to_owned(), render_device.create_sampler(&descriptor));
+        SamplerHandle::from_name(name)
     }
 
     /// Add a new compute pass to your worker.
     /// They will run sequentially in the order you insert them.
-    pub fn add_pass<S: ComputeShader>(&mut self, workgroups: [u32; 3], vars: &[&str]) -> &mut Self {
-        if !self.cached_pipeline_ids.contains_key(&S::TYPE_UUID) {
-            let pipeline_cache = self.world.resource::<AppPipelineCache>();
-
-            let asset_server = self.world.resource::<AssetServer>();
-            let shader = match S::shader() {
-                ShaderRef::Default => None,
-                ShaderRef::Handle(handle) => Some(handle),
-                ShaderRef::Path(path) => Some(asset_server.load(path)),
-            }
-            .unwrap();
-
-            let cached_id = pipeline_cache.queue_app_compute_pipeline(ComputePipelineDescriptor {
-                label: None,
-                layout: S::layouts().to_vec(),
-                push_constant_ranges: S::push_constant_ranges().to_vec(),
-                shader_defs: S::shader_defs().to_vec(),
-                entry_point: Cow::Borrowed(S::entry_point()),
-                shader,
-            });
+    ///
+    /// `vars` are bound in order to `@group(0) @binding(0..)`.
+    pub fn add_pass<S: ComputeShader>(
+        &mut self,
+        workgroups: [u32; 3],
+        vars: &[&dyn ResourceHandle],
+    ) -> &mut Self {
+        self.push_pass::<S>(
+            Dispatch::Direct(workgroups),
+            vars,
+            pipeline_key::<S>(None, &[]),
+        )
+    }
+
+    /// Add a new compute pass to your worker, running the `entry_point` of the shader
+    /// instead of [`ComputeShader::entry_point`].
+    /// They will run sequentially in the order you insert them.
+    ///
+    /// Each entry point of a shader has its own pipeline, so a single module can hold
+    /// all the stages of an algorithm.
+    /// `vars` are bound in order to `@group(0) @binding(0..)`.
+    pub fn add_pass_entry<S: ComputeShader>(
+        &mut self,
+        entry_point: impl Into<Cow<'static, str>>,
+        workgroups: [u32; 3],
+        vars: &[&dyn ResourceHandle],
+    ) -> &mut Self {
+        let key = pipeline_key::<S>(Some(entry_point.into()), &[]);
+        self.push_pass::<S>(Dispatch::Direct(workgroups), vars, key)
+    }
+
+    /// Add a new compute pass to your worker, specialized with `shader_defs`
+    /// in addition to [`ComputeShader::shader_defs`].
+    /// They will run sequentially in the order you insert them.
+    ///
+    /// Passes of the same shader with different defs have their own pipeline,
+    /// the defs can be changed later with [`AppComputeWorker::set_shader_defs`].
+    /// `vars` are bound in order to `@group(0) @binding(0..)`.
+    pub fn add_pass_with_defs<S: ComputeShader>(
+        &mut self,
+        shader_defs: &[ShaderDefVal],
+        workgroups: [u32; 3],
+        vars: &[&dyn ResourceHandle],
+    ) -> &mut Self {
+        let key = pipeline_key::<S>(None, shader_defs);
+        self.push_pass::<S>(Dispatch::Direct(workgroups), vars, key)
+    }
+
+    /// Add a new compute pass to your worker, processing `count` elements along `x`.
+    /// They will run sequentially in the order you insert them.
+    ///
+    /// The number of workgroups is derived from the `@workgroup_size` of the shader's
+    /// entry point, and can be changed later with [`AppComputeWorker::set_element_count`].
+    /// `vars` are bound in order to `@group(0) @binding(0..)`.
+    pub fn add_pass_for_elements<S: ComputeShader>(
+        &mut self,
+        count: u32,
+        vars: &[&dyn ResourceHandle],
+    ) -> &mut Self {
+        self.push_pass::<S>(
+            Dispatch::Elements(count),
+            vars,
+            pipeline_key::<S>(None, &[]),
+        )
+    }
 
-            self.cached_pipeline_ids.insert(S::TYPE_UUID, cached_id);
+    /// Add a new compute pass to your worker, whose workgroup counts are read from `args`
+    /// when it runs, see [`Self::add_indirect_args`].
+    /// They will run sequentially in the order you insert them.
+    ///
+    /// `vars` are bound in order to `@group(0) @binding(0..)`.
+    pub fn add_pass_indirect<S: ComputeShader>(
+        &mut self,
+        args: &BufferHandle<DispatchIndirectArgs>,
+        vars: &[&dyn ResourceHandle],
+    ) -> &mut Self {
+        let dispatch = Dispatch::Indirect {
+            buffer: args.name().to_owned(),
+            offset: 0,
+        };
+        self.push_pass::<S>(dispatch, vars, pipeline_key::<S>(None, &[]))
+    }
+
+    /// Add a new compute pass to your worker, with push constants initialized to `push_constants`.
+    /// They will run sequentially in the order you insert them.
+    ///
+    /// Unless [`ComputeShader::push_constant_ranges`] says otherwise, the push constants
+    /// are visible from offset 0 in the compute stage. The render device must have been
+    /// created with [`wgpu::Features::PUSH_CONSTANTS`] and a large enough `max_push_constant_size`.
+    /// `vars` are bound in order to `@group(0) @binding(0..)`.
+    ///
+    /// Panics if the size of `T` isn't a multiple of 4 bytes, or doesn't fit the compute range.
+    pub fn add_pass_with_push_constants<S: ComputeShader, T: NoUninit>(
+        &mut self,
+        workgroups: [u32; 3],
+        vars: &[&dyn ResourceHandle],
+        push_constants: &T,
+    ) -> PushConstantsHandle<T> {
+        let size = size_of::<T>() as u32;
+        assert!(
+            size % wgpu::PUSH_CONSTANT_ALIGNMENT == 0,
+            "push constants must be a multiple of {} bytes, found {size} bytes",
+            wgpu::PUSH_CONSTANT_ALIGNMENT
+        );
+
+        let mut key = pipeline_key::<S>(None, &[]);
+        if key.push_constant_ranges.is_empty() {
+            key.push_constant_ranges = vec![PushConstantRange {
+                stages: ShaderStages::COMPUTE,
+                range: 0..size,
+            }];
         }
+        let Some(range) = compute_push_constants(&key.push_constant_ranges) else {
+            panic!(
+                "`push_constant_ranges` of {} has no compute range",
+                std::any::type_name::<S>()
+            );
+        };
+        assert!(
+            range.start + size <= range.end,
+            "push constants of {size} bytes don't fit the compute range {range:?}"
+        );
+
+        self.push_pass::<S>(Dispatch::Direct(workgroups), vars, key);
+        let Some(Step::ComputePass(compute_pass)) = self.steps.last_mut() else {
+            unreachable!();
+        };
+        compute_pass.push_constants = bytes_of(push_constants).to_vec();
+        PushConstantsHandle::new(compute_pass.id)
+    }
+
+    fn push_pass<S: ComputeShader>(
+        &mut self,
+        dispatch: Dispatch,
+        vars: &[&dyn ResourceHandle],
+        key: PipelineKey,
+    ) -> &mut Self {
+        self.add_pipeline_descriptor::<S>();
 
         self.steps.push(Step::ComputePass(ComputePass {
-            workgroups,
-            vars: vars.iter().map(|a| String::from(*a)).collect(),
+            id: self.passes,
+            dispatch,
+            groups: vec![positional_bindings(vars)],
+            named: None,
+            push_constants: vec![],
+            push_constant_ranges: key.push_constant_ranges,
             shader_uuid: S::TYPE_UUID,
+            shader_name: std::any::type_name::<S>(),
+            shader_defs: key.shader_defs,
+            entry_point: key.entry_point,
         }));
+        self.passes += 1;
+        self
+    }
+
+    /// Add the descriptor the pipelines of `S` are specialized from, unless a previous pass did.
+    /// They are queued once the worker is built, when the passes can't change anymore.
+    fn add_pipeline_descriptor<S: ComputeShader>(&mut self) {
+        self.pipeline_descriptors
+            .entry(S::TYPE_UUID)
+            .or_insert_with(|| {
+                // A `ComputeRuntime` has no asset server, its shaders are added by handle
+                let shader = match S::shader() {
+                    ShaderRef::Default => None,
+                    ShaderRef::Handle(handle) => Some(handle),
+                    ShaderRef::Path(path) => match self.world.get_resource::<AssetServer>() {
+                        Some(asset_server) => Some(asset_server.load(path)),
+                        None => panic!("Shader {path} can't be loaded without an `AssetServer`"),
+                    },
+                }
+                .unwrap();
+
+                ComputePipelineDescriptor {
+                    label: None,
+                    layout: S::layouts().to_vec(),
+                    push_constant_ranges: vec![],
+                    shader_defs: S::shader_defs().to_vec(),
+                    entry_point: Cow::Borrowed(S::entry_point()),
+                    shader,
+                }
+            });
+    }
+
+    /// Run the `entry_point` of the shader in the last pass added, instead of the one it was
+    /// added with, e.g. to dispatch it with [`Self::add_pass_for_elements`].
+    ///
+    /// Each entry point of a shader has its own pipeline, see [`Self::add_pass_entry`].
+    pub fn with_entry_point(&mut self, entry_point: impl Into<Cow<'static, str>>) -> &mut Self {
+        let Some(Step::ComputePass(compute_pass)) = self.steps.last_mut() else {
+            panic!("`with_entry_point` must be called right after adding a pass");
+        };
+        compute_pass.entry_point = entry_point.into();
+        self
+    }
+
+    /// Specialize the last pass added with `shader_defs` in addition to
+    /// [`ComputeShader::shader_defs`], instead of the ones it was added with.
+    ///
+    /// Passes with different defs have their own pipeline, see [`Self::add_pass_with_defs`].
+    pub fn with_shader_defs(&mut self, shader_defs: &[ShaderDefVal]) -> &mut Self {
+        let Some(Step::ComputePass(compute_pass)) = self.steps.last_mut() else {
+            panic!("`with_shader_defs` must be called right after adding a pass");
+        };
+        compute_pass.shader_defs = shader_defs.to_vec();
+        self
+    }
+
+    /// Bind `vars` in order to `@group(group) @binding(0..)` of the last pass added,
+    /// replacing the `vars` it was added with for `@group(0)`.
+    ///
+    /// Groups in between that haven't been declared are bound empty.
+    pub fn bind_group(&mut self, group: u32, vars: &[&dyn ResourceHandle]) -> &mut Self {
+        let Some(Step::ComputePass(compute_pass)) = self.steps.last_mut() else {
+            panic!("`bind_group` must be called right after adding a pass");
+        };
+
+        let group = group as usize;
+        if compute_pass.groups.len() <= group {
+            compute_pass.groups.resize(group + 1, vec![]);
+        }
+        compute_pass.groups[group] = positional_bindings(vars);
+        self
+    }
+
+    /// Bind the buffers of the last pass added to the global variables of the same name in
+    /// the shader, whatever their `@group` and `@binding`, instead of by position.
+    ///
+    /// Bindings are resolved once the pipeline is ready, buffers the shader doesn't use are an error.
+    pub fn bind_by_name(&mut self) -> &mut Self {
+        let Some(Step::ComputePass(compute_pass)) = self.steps.last_mut() else {
+            panic!("`bind_by_name` must be called right after adding a pass");
+        };
+
+        let vars = compute_pass
+            .groups
+            .drain(..)
+            .flatten()
+            .map(|binding| binding.resource)
+            .collect();
+        compute_pass.named = Some(vars);
+        self
+    }
+
+    /// Swap two buffers of the same type, e.g. to ping-pong between a source and
+    /// a destination buffer.
+    pub fn add_swap<T>(
+        &mut self,
+        buffer_a: &BufferHandle<T>,
+        buffer_b: &BufferHandle<T>,
+    ) -> &mut Self {
+        self.steps.push(Step::Swap(
+            buffer_a.name().to_owned(),
+            buffer_b.name().to_owned(),
+        ));
+        self
+    }
+
+    /// Copy the contents of `src` to `dst`, or as much as fits in it.
+    ///
+    /// Panics if `src` and `dst` are the same buffer.
+    pub fn add_copy<T>(&mut self, src: &BufferHandle<T>, dst: &BufferHandle<T>) -> &mut Self {
+        assert_ne!(
+            src.name(),
+            dst.name(),
+            "`add_copy` can't copy a buffer to itself"
+        );
+        self.steps.push(Step::Copy {
+            src: src.name().to_owned(),
+            dst: dst.name().to_owned(),
+            range: None,
+        });
         self
     }
 
-    pub fn add_swap(&mut self, buffer_a: &str, buffer_b: &str) -> &mut Self {
-        self.steps
-            .push(Step::Swap(buffer_a.to_owned(), buffer_b.to_owned()));
+    /// Copy the elements in `range` of `src` to the same elements of `dst`.
+    ///
+    /// Panics if `src` and `dst` are the same buffer.
+    pub fn add_copy_range<T>(
+        &mut self,
+        src: &BufferHandle<Vec<T>>,
+        dst: &BufferHandle<Vec<T>>,
+        range: Range<usize>,
+    ) -> &mut Self {
+        assert_ne!(
+            src.name(),
+            dst.name(),
+            "`add_copy_range` can't copy a buffer to itself"
+        );
+        let element_size = size_of::<T>() as u64;
+        self.steps.push(Step::Copy {
+            src: src.name().to_owned(),
+            dst: dst.name().to_owned(),
+            range: Some(range.start as u64 * element_size..range.end as u64 * element_size),
+        });
         self
     }
 
+    /// Fill `target` buffer with zeros.
+    pub fn add_clear<T>(&mut self, target: &BufferHandle<T>) -> &mut Self {
+        self.steps.push(Step::Clear(target.name().to_owned()));
+        self
+    }
+
+    /// Run the steps added by `steps` `count` times in a row, e.g. to substep a simulation.
+    ///
+    /// ```ignore
+    /// builder.add_repeat(4, |builder| {
+    ///     builder
+    ///         .add_pass::<DensityShader>(workgroups, &[&params, &particles_src, &density])
+    ///         .add_pass::<StateEquationShader>(workgroups, &[&params, &particles_src, &density, &particles_dst])
+    ///         .add_swap(&particles_src, &particles_dst);
+    /// });
+    /// ```
+    pub fn add_repeat(&mut self, count: u32, steps: impl FnOnce(&mut Self)) -> &mut Self {
+        let steps = self.nested_steps(steps);
+        self.steps.push(Step::Repeat { count, steps });
+        self
+    }
+
+    /// Run the steps added by `steps` only when `flag` is set with
+    /// [`AppComputeWorker::set_flag`]. Flags are unset by default.
+    pub fn add_if(&mut self, flag: &str, steps: impl FnOnce(&mut Self)) -> &mut Self {
+        self.flags.entry(flag.to_owned()).or_insert(false);
+        let steps = self.nested_steps(steps);
+        self.steps.push(Step::If {
+            flag: flag.to_owned(),
+            steps,
+        });
+        self
+    }
+
+    /// Collect the steps added by `steps` instead of appending them to this builder's.
+    fn nested_steps(&mut self, steps: impl FnOnce(&mut Self)) -> Vec<Step> {
+        let outer = std::mem::take(&mut self.steps);
+        steps(self);
+        std::mem::replace(&mut self.steps, outer)
+    }
+
     /// The worker will run every frames.
     /// This is the default mode.
     pub fn continuous(&mut self) -> &mut Self {
@@ -259,8 +761,332 @@ impl<'a, W: ComputeWorker> AppComputeWorkerBuilder<'a, W> {
         self
     }
 
+    /// Number of runs that can be in flight at once, each with its own set of staging buffers.
+    ///
+    /// With `1` (the default), the worker waits for the GPU at the end of every run.
+    /// With more, the worker never blocks: the results of a run can be read
+    /// one or more frames later, when [`AppComputeWorker::ready`] returns `true`.
+    pub fn frames_in_flight(&mut self, frames_in_flight: usize) -> &mut Self {
+        assert!(frames_in_flight > 0, "At least one frame must be in flight");
+        self.frames_in_flight = frames_in_flight;
+        self
+    }
+
+    /// Measure how long the worker takes and publish it as [`Diagnostic`](bevy::diagnostic::Diagnostic)s:
+    /// `compute/<Shader>` for the passes of each shader and `compute/<Worker>` for whole runs,
+    /// in milliseconds.
+    ///
+    /// Passes are timed on the GPU when the device supports `TIMESTAMP_QUERY`, otherwise only
+    /// whole runs are, on the CPU from their submission to their completion.
+    pub fn timings(&mut self) -> &mut Self {
+        self.timings_with_policy(ReadbackPolicy::EveryRun)
+    }
+
+    /// Like [`Self::timings`], but only time the runs read back according to `policy`,
+    /// e.g. every few runs to keep the timestamps from being mapped every frame.
+    /// Other runs can be timed with [`AppComputeWorker::request_timings`].
+    pub fn timings_with_policy(&mut self, policy: ReadbackPolicy) -> &mut Self {
+        self.timings = Some(policy);
+        self
+    }
+
+    /// Keep the results of the last completed run readable while the next runs are in flight,
+    /// instead of only during the frame after it completed.
+    ///
+    /// This holds one set of staging buffers, so at least 2 frames must be in flight.
+    pub fn keep_last_results(&mut self) -> &mut Self {
+        self.keep_last_results = true;
+        self
+    }
+
+    /// Call `callback` with the worker and the index of the run every time a run completes,
+    /// while its results can be read.
+    pub fn on_finished(
+        &mut self,
+        callback: impl Fn(&AppComputeWorker<W>, u64) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.on_finished.push(Arc::new(callback));
+        self
+    }
+
+    /// Run `system` every time a run completes, while its results can be read.
+    ///
+    /// Systems can also react to the [`ComputeWorkerFinished<W>`](super::worker::ComputeWorkerFinished)
+    /// event, e.g. with `run_if(on_event::<ComputeWorkerFinished<W>>())`.
+    pub fn on_finished_system<M>(
+        &mut self,
+        system: impl IntoSystem<(), (), M> + 'static,
+    ) -> &mut Self {
+        let id = self.world.register_system(system);
+        self.on_finished_systems.push(id);
+        self
+    }
+
     /// Build an [`AppComputeWorker<W>`] from this builder.
     pub fn build(&self) -> AppComputeWorker<W> {
         AppComputeWorker::from(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::{prelude::Resource, reflect::TypeUuid};
+    use wgpu::Features;
+
+    use super::*;
+    use crate::compute::harness::{load_wgsl, shader_handle, ComputeHarness};
+
+    const PUSH_CONSTANTS_SHADER: &str = r"
+        @group(0) @binding(0) var<storage, read_write> values: array<u32, 4>;
+        var<push_constant> factor: f32;
+
+        @compute @workgroup_size(4)
+        fn double(@builtin(global_invocation_id) id: vec3<u32>) {
+            values[id.x] *= 2u;
+        }
+
+        @compute @workgroup_size(4)
+        fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+            values[id.x] = u32(f32(values[id.x]) * factor);
+        }
+    ";
+
+    #[derive(TypeUuid)]
+    #[uuid = "4b3f0b52-4f1d-4c7e-9a5e-2f7c1d2e8a61"]
+    struct PushConstantsShader;
+
+    impl ComputeShader for PushConstantsShader {
+        fn shader() -> ShaderRef {
+            shader_handle::<Self>()
+        }
+    }
+
+    #[derive(Resource)]
+    struct Factor(PushConstantsHandle<f32>);
+
+    struct PushConstantsWorker;
+
+    impl ComputeWorker for PushConstantsWorker {
+        fn build(world: &mut World) -> AppComputeWorker<Self> {
+            let mut builder = AppComputeWorkerBuilder::new(world);
+            let values = builder.add_staging("values", &vec![1u32, 2, 3, 4]);
+            // The same shader without push constants first, so its pipelines are keyed apart
+            let factor = builder
+                .add_pass_entry::<PushConstantsShader>("double", [1, 1, 1], &[&values])
+                .add_pass_with_push_constants::<PushConstantsShader, f32>(
+                    [1, 1, 1],
+                    &[&values],
+                    &3.0,
+                );
+            builder.world.insert_resource(Factor(factor));
+            builder.one_shot().build()
+        }
+    }
+
+    /// Push constants visible from offset 4, only used to build passes.
+    #[derive(TypeUuid)]
+    #[uuid = "c7d15a2e-92b4-4e1f-8c36-5b0f4e7a9d28"]
+    struct OffsetShader;
+
+    impl ComputeShader for OffsetShader {
+        fn shader() -> ShaderRef {
+            shader_handle::<Self>()
+        }
+
+        fn push_constant_ranges<'a>() -> &'a [PushConstantRange] {
+            &[PushConstantRange {
+                stages: ShaderStages::COMPUTE,
+                range: 4..8,
+            }]
+        }
+    }
+
+    const CELLS_SHADER: &str = r"
+        @group(0) @binding(0) var<storage, read_write> cells: array<u32, 4>;
+
+        @compute @workgroup_size(2)
+        fn count(@builtin(global_invocation_id) id: vec3<u32>) {
+        #ifdef TWICE
+            cells[id.x] += 2u;
+        #else
+            cells[id.x] += 1u;
+        #endif
+        }
+    ";
+
+    /// A shader without the default entry point, whose passes must all name theirs.
+    #[derive(TypeUuid)]
+    #[uuid = "9e2a7c41-3d5b-4f80-b6e1-7a4c2d9f0b13"]
+    struct CellsShader;
+
+    impl ComputeShader for CellsShader {
+        fn shader() -> ShaderRef {
+            shader_handle::<Self>()
+        }
+    }
+
+    struct CellsWorker;
+
+    impl ComputeWorker for CellsWorker {
+        fn build(world: &mut World) -> AppComputeWorker<Self> {
+            let mut builder = AppComputeWorkerBuilder::new(world);
+            let cells = builder.add_staging("cells", &vec![1u32, 2, 3, 4]);
+            builder
+                .add_pass_for_elements::<CellsShader>(4, &[&cells])
+                .with_entry_point("count")
+                .with_shader_defs(&[ShaderDefVal::Bool("TWICE".to_owned(), true)])
+                .add_pass_for_elements::<CellsShader>(4, &[&cells])
+                .with_entry_point("count");
+            builder.one_shot().build()
+        }
+    }
+
+    const INDIRECT_SHADER: &str = r"
+        struct DispatchIndirectArgs { x: u32, y: u32, z: u32 }
+
+        @group(0) @binding(0) var<storage, read_write> values: array<u32>;
+        @group(0) @binding(1) var<storage, read_write> args: DispatchIndirectArgs;
+
+        @compute @workgroup_size(1)
+        fn size() {
+            args.x = arrayLength(&values) / 4u;
+        }
+
+        @compute @workgroup_size(4)
+        fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+            values[id.x] += 1u;
+        }
+    ";
+
+    #[derive(TypeUuid)]
+    #[uuid = "2c9f4e7b-81a3-4d56-9e0b-6f3a5d8c1e24"]
+    struct IndirectShader;
+
+    impl ComputeShader for IndirectShader {
+        fn shader() -> ShaderRef {
+            shader_handle::<Self>()
+        }
+    }
+
+    struct IndirectWorker;
+
+    impl ComputeWorker for IndirectWorker {
+        fn build(world: &mut World) -> AppComputeWorker<Self> {
+            let mut builder = AppComputeWorkerBuilder::new(world);
+            let values = builder.add_staging("values", &vec![0u32; 8]);
+            // Sized by the first pass, nothing would run otherwise
+            let args = builder.add_indirect_args("args", &DispatchIndirectArgs::new(0, 1, 1));
+            builder
+                .add_pass::<IndirectShader>([1, 1, 1], &[&values, &args])
+                .with_entry_point("size")
+                .add_pass_indirect::<IndirectShader>(&args, &[&values]);
+            builder.one_shot().build()
+        }
+    }
+
+    #[test]
+    fn test_indirect_dispatch() {
+        let mut harness = ComputeHarness::<IndirectWorker>::new(|app| {
+            load_wgsl::<IndirectShader>(app, INDIRECT_SHADER)
+        });
+        harness.run(1);
+
+        let values = BufferHandle::<Vec<u32>>::from_name("values");
+        assert_eq!(harness.read_vec(&values), [1; 8]);
+    }
+
+    const GROUPS_SHADER: &str = r"
+        @group(0) @binding(0) var<storage, read_write> values: array<u32, 4>;
+        @group(1) @binding(0) var<storage, read> addends: array<u32, 4>;
+
+        @compute @workgroup_size(4)
+        fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+            values[id.x] += addends[id.x];
+        }
+    ";
+
+    #[derive(TypeUuid)]
+    #[uuid = "a71e3c58-0d94-4b2f-85c6-9e1b4f7d2a03"]
+    struct GroupsShader;
+
+    impl ComputeShader for GroupsShader {
+        fn shader() -> ShaderRef {
+            shader_handle::<Self>()
+        }
+    }
+
+    struct GroupsWorker;
+
+    impl ComputeWorker for GroupsWorker {
+        fn build(world: &mut World) -> AppComputeWorker<Self> {
+            let mut builder = AppComputeWorkerBuilder::new(world);
+            let values = builder.add_staging("values", &vec![1u32, 2, 3, 4]);
+            let addends = builder.add_storage("addends", &vec![10u32, 20, 30, 40]);
+            builder
+                .add_pass::<GroupsShader>([1, 1, 1], &[&values])
+                .bind_group(1, &[&addends]);
+            builder.one_shot().build()
+        }
+    }
+
+    #[test]
+    fn test_several_bind_groups() {
+        let mut harness = ComputeHarness::<GroupsWorker>::new(|app| {
+            load_wgsl::<GroupsShader>(app, GROUPS_SHADER)
+        });
+        harness.run(1);
+
+        let values = BufferHandle::<Vec<u32>>::from_name("values");
+        assert_eq!(harness.read_vec(&values), [11, 22, 33, 44]);
+    }
+
+    #[test]
+    fn test_push_constants_with_reflected_layout() {
+        let mut harness = ComputeHarness::<PushConstantsWorker>::new(|app| {
+            load_wgsl::<PushConstantsShader>(app, PUSH_CONSTANTS_SHADER)
+        });
+        assert!(
+            harness
+                .render_device()
+                .features()
+                .contains(Features::PUSH_CONSTANTS),
+            "The adapter doesn't support push constants"
+        );
+        harness.run(1);
+
+        let values = BufferHandle::<Vec<u32>>::from_name("values");
+        assert_eq!(harness.read_vec(&values), [6, 12, 18, 24]);
+
+        let factor = harness.resource::<Factor>().0;
+        harness.worker_mut().set_push_constants(&factor, &0.25);
+        harness.run(1);
+        assert_eq!(harness.read_vec(&values), [3, 6, 9, 12]);
+    }
+
+    #[test]
+    #[should_panic(expected = "don't fit the compute range")]
+    fn test_push_constants_out_of_range() {
+        let mut world = World::new();
+        let mut builder = AppComputeWorkerBuilder::<PushConstantsWorker>::new(&mut world);
+        builder.add_pass_with_push_constants::<OffsetShader, [f32; 2]>([1, 1, 1], &[], &[0.0; 2]);
+    }
+
+    #[test]
+    #[should_panic(expected = "can't copy a buffer to itself")]
+    fn test_copy_to_itself() {
+        let mut world = World::new();
+        let mut builder = AppComputeWorkerBuilder::<CellsWorker>::new(&mut world);
+        let cells = BufferHandle::<Vec<u32>>::from_name("cells");
+        builder.add_copy_range(&cells, &cells, 0..2);
+    }
+
+    #[test]
+    fn test_entry_point_for_elements() {
+        let mut harness =
+            ComputeHarness::<CellsWorker>::new(|app| load_wgsl::<CellsShader>(app, CELLS_SHADER));
+        harness.run(1);
+
+        let cells = BufferHandle::<Vec<u32>>::from_name("cells");
+        assert_eq!(harness.read_vec(&cells), [4, 5, 6, 7]);
+    }
+}
@@ -78,6 +78,13 @@ struct Density {
 
 struct BoidWorker;
 
+/// Handles to the [`BoidWorker`] buffers we read from or write to.
+#[derive(Resource)]
+struct BoidBuffers {
+    params: BufferHandle<Parameters>,
+    particles_dst: BufferHandle<Vec<Particle>>,
+}
+
 impl ComputeWorker for BoidWorker {
     fn build(world: &mut World) -> AppComputeWorker<Self> {
         let params = world.get_resource::<Parameters>().unwrap().clone();
@@ -100,30 +107,46 @@ impl ComputeWorker for BoidWorker {
             });
         }
 
-        AppComputeWorkerBuilder::new(world)
-            .add_uniform("params", &params)
-            .add_staging("particles_src", &initial_boids_data)
-            .add_staging("particles_dst", &initial_boids_data)
-            .add_staging(
-                "density",
-                &vec![
-                    Density {
-                        value: 0.0,
-                        number: 0.0
-                    };
-                    NUM_PARTICLES as usize
-                ],
-            )
-            .add_pass::<shaders::DensityShader>(
-                [NUM_PARTICLES / 32 + 1, 1, 1],
-                &["params", "particles_src", "density"],
+        let mut builder = AppComputeWorkerBuilder::new(world);
+
+        let params = builder.add_uniform("params", &params);
+        let particles_src = builder.add_staging("particles_src", &initial_boids_data);
+        let particles_dst = builder.add_staging("particles_dst", &initial_boids_data);
+        let density = builder.add_staging(
+            "density",
+            &vec![
+                Density {
+                    value: 0.0,
+                    number: 0.0
+                };
+                NUM_PARTICLES as usize
+            ],
+        );
+
+        let worker = builder
+            .add_pass_for_elements::<shaders::DensityShader>(
+                NUM_PARTICLES,
+                &[&params, &particles_src, &density],
             )
-            .add_pass::<shaders::StateEquationShader>(
-                [NUM_PARTICLES / 32 + 1, 1, 1],
-                &["params", "particles_src", "density", "particles_dst"],
+            .bind_by_name()
+            .add_pass_for_elements::<shaders::StateEquationShader>(
+                NUM_PARTICLES,
+                &[&params, &particles_src, &density, &particles_dst],
             )
-            .add_swap("particles_src", "particles_dst")
-            .build()
+            .bind_by_name()
+            .add_swap(&particles_src, &particles_dst)
+            .readback_policy(&particles_src, ReadbackPolicy::Never)
+            .readback_policy(&density, ReadbackPolicy::OnRequest)
+            .frames_in_flight(2)
+            .timings()
+            .build();
+
+        world.insert_resource(BoidBuffers {
+            params,
+            particles_dst,
+        });
+
+        worker
     }
 }
 
@@ -145,7 +168,10 @@ fn main() {
     .add_plugins(AppComputePlugin)
     .add_plugins(AppComputeWorkerPlugin::<BoidWorker>::default())
     .add_systems(Startup, setup)
-    .add_systems(Update, move_entities);
+    .add_systems(
+        Update,
+        move_entities.run_if(on_event::<ComputeWorkerFinished<BoidWorker>>()),
+    );
 
     // load_shaders(&mut app);
 
@@ -215,32 +241,29 @@ fn move_entities(
     time: Res<Time>,
     mut parameters: ResMut<Parameters>,
     mut worker: ResMut<AppComputeWorker<BoidWorker>>,
+    buffers: Res<BoidBuffers>,
     mut q_boid: Query<(&mut Transform, &BoidEntity), With<BoidEntity>>,
 ) {
-    if !worker.ready() {
-        return;
-    }
-
-    // for x in worker.read_vec::<Density>("density") {
-    //     println!("{:?}", x);
-    // }
-
-    let boids = worker.read_vec::<Particle>("particles_dst");
-
-    parameters.delta_time = time.delta_seconds() * 0.1;
-    worker.write("params", parameters.as_ref());
-
+    let boids = worker.read_slice(&buffers.particles_dst);
+    let positions: &[Particle] = &boids;
     q_boid
         .par_iter_mut()
         .for_each(|(mut transform, boid_entity)| {
-            transform.translation = boids[boid_entity.0].position.extend(0.0);
+            transform.translation = positions[boid_entity.0].position.extend(0.0);
         });
+    // The staging buffer stays borrowed until the view is dropped
+    drop(boids);
+
+    parameters.delta_time = time.delta_seconds() * 0.1;
+    worker.write(&buffers.params, parameters.as_ref());
 }
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use super::*;
-    use bevy::{app::AppExit, render::RenderPlugin, window::ExitCondition, winit::WinitPlugin};
+    use crate::compute::harness::ComputeHarness;
     use rand::Rng;
 
     fn random_points(num_points: usize) -> Vec<Vec2> {
@@ -259,7 +282,9 @@ mod tests {
     type CellKey = u32;
     type ParticleIndex = u32;
 
-    #[derive(ShaderType, Pod, Zeroable, Clone, Copy, Debug, Default)]
+    #[derive(
+        ShaderType, Pod, Zeroable, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord,
+    )]
     #[repr(C)]
     struct SpatialIndexEntry {
         cell_key: CellKey,
@@ -268,6 +293,14 @@ mod tests {
 
     struct SpatialIndexWorker;
 
+    #[derive(Resource)]
+    struct SpatialIndexBuffers {
+        /// Entries before sorting them.
+        unsorted: BufferHandle<Vec<SpatialIndexEntry>>,
+        entries: BufferHandle<Vec<SpatialIndexEntry>>,
+        start_indices: BufferHandle<Vec<u32>>,
+    }
+
     impl ComputeWorker for SpatialIndexWorker {
         fn build(world: &mut World) -> AppComputeWorker<Self> {
             let params = world.get_resource::<Parameters>().unwrap().clone();
@@ -298,98 +331,83 @@ mod tests {
             let mut start_indices: Vec<u32> = Vec::with_capacity(NUM_PARTICLES);
             start_indices.resize(NUM_PARTICLES, u32::MAX);
 
-            AppComputeWorkerBuilder::new(world)
-                .add_uniform("params", &params)
-                .add_staging("positions", &positions)
-                .add_staging("entries", &entries)
-                .add_staging("start_indices", &start_indices)
-                .add_pass::<shaders::SpatialComputeEntriesShader>(
-                    [NUM_PARTICLES as u32 / 256 + 1, 1, 1],
-                    &["params", "positions", "entries"],
+            let mut builder = AppComputeWorkerBuilder::new(world);
+
+            let params = builder.add_uniform("params", &params);
+            let positions = builder.add_staging("positions", &positions);
+            let unsorted = builder.add_staging("unsorted", &entries);
+            let entries = builder.add_staging("entries", &entries);
+            let start_indices = builder.add_staging("start_indices", &start_indices);
+
+            let worker = builder
+                .add_pass_for_elements::<shaders::SpatialComputeEntriesShader>(
+                    NUM_PARTICLES as u32,
+                    &[&params, &positions, &entries],
                 )
+                .add_copy(&entries, &unsorted)
                 .add_pass::<shaders::SpatialSortEntriesShader>(
                     [1, 1, 1],
-                    &["entries"],
+                    &[&entries],
                 )
-                .add_pass::<shaders::SpatialComputeStartIndices>(
-                    [NUM_PARTICLES as u32 / 256 + 1, 1, 1],
-                    &["entries", "start_indices"],
+                .add_pass_for_elements::<shaders::SpatialComputeStartIndices>(
+                    NUM_PARTICLES as u32,
+                    &[&entries, &start_indices],
                 )
                 .one_shot()
-                .build()
-        }
-    }
-
-    fn start_compute_worker(mut worker: ResMut<AppComputeWorker<SpatialIndexWorker>>) {
-        // assert!(worker.ready());
-        println!("Starting compute worker");
-        worker.execute();
-    }
+                .build();
 
-    fn print_compute_shader_results(
-        worker: ResMut<AppComputeWorker<SpatialIndexWorker>>,
-        mut exit: EventWriter<AppExit>,
-    ) {
-        if !worker.ready() {
-            println!("Compute worker not ready");
-            return;
-        }
+            world.insert_resource(SpatialIndexBuffers {
+                unsorted,
+                entries,
+                start_indices,
+            });
 
-        println!("Done.");
-        println!("Entries");
-        for (i, x) in worker.read_vec::<SpatialIndexEntry>("entries").into_iter().enumerate() {  
-            println!("{}: {:?}", i, x);
-        }
-        println!("Start indices");
-        for (i, x) in worker.read_vec::<u32>("start_indices").into_iter().enumerate() {
-            println!("{}: {:?}", i, x);
+            worker
         }
-        exit.send(AppExit);
-
-        // for x in worker.read_vec::<Density>("density") {
-        //     println!("{:?}", x);
-        // }
-
-        // let boids = worker.read_vec::<Particle>("particles_dst");
-
-        // parameters.delta_time = time.delta_seconds() * 0.1;
-        // worker.write("params", parameters.as_ref());
-
-        // q_boid
-        //     .par_iter_mut()
-        //     .for_each(|(mut transform, boid_entity)| {
-        //         transform.translation = boids[boid_entity.0].position.extend(0.0);
-        //     });
     }
 
     #[test]
     fn test_gpu_sort() {
-        let mut app = App::new();
-        app.add_plugins((DefaultPlugins
-            .set(WindowPlugin {
-                // primary_window: None,
-                // exit_condition: ExitCondition::DontExit,
-                ..default()
-            })
-            .set(WinitPlugin {
-                run_on_any_thread: true,
-            }),))
-            .add_plugins(AppComputePlugin)
-            .add_plugins(AppComputeWorkerPlugin::<SpatialIndexWorker>::default())
+        let mut harness = ComputeHarness::<SpatialIndexWorker>::new(|app| {
+            shaders::ParticleShader::load_shader(app);
+            shaders::KernelShader::load_shader(app);
+
+            // Spatial index
+            shaders::SpatialCommonShader::load_shader(app);
+            shaders::SpatialComputeEntriesShader::load_shader(app);
+            shaders::SpatialSortEntriesShader::load_shader(app);
+            shaders::SpatialComputeStartIndices::load_shader(app);
+        });
+        // A single workgroup sorts every entry, which is slow on a software adapter
+        harness
+            .timeout(Duration::from_secs(30))
             .insert_resource(Parameters::default())
-            .add_systems(Startup, start_compute_worker)
-            .add_systems(Update, print_compute_shader_results);
-
-        shaders::ParticleShader::load_shader(&mut app);
-        shaders::KernelShader::load_shader(&mut app);
-
-        // Spatial index
-        shaders::SpatialCommonShader::load_shader(&mut app);
-        shaders::SpatialComputeEntriesShader::load_shader(&mut app);
-        shaders::SpatialSortEntriesShader::load_shader(&mut app);
-        shaders::SpatialComputeStartIndices::load_shader(&mut app);
-
-
-        app.run();
+            .run(1);
+
+        let buffers = harness.resource::<SpatialIndexBuffers>();
+        let unsorted = harness.read_vec(&buffers.unsorted);
+        let entries = harness.read_vec(&buffers.entries);
+        assert!(unsorted
+            .iter()
+            .enumerate()
+            .all(|(index, entry)| entry.particle_index == index as u32));
+        assert!(entries.windows(2).all(|pair| pair[0].cell_key <= pair[1].cell_key));
+
+        // Sorting moves whole entries, each particle keeps its cell key
+        let mut pairs = entries.clone();
+        pairs.sort();
+        let mut expected = unsorted;
+        expected.sort();
+        assert_eq!(pairs, expected);
+
+        // Each cell with entries starts at its first one
+        let start_indices = harness.read_vec(&buffers.start_indices);
+        for (cell_key, start_index) in start_indices.into_iter().enumerate() {
+            let first = entries
+                .iter()
+                .position(|entry| entry.cell_key == cell_key as u32)
+                .map_or(u32::MAX, |index| index as u32);
+            assert_eq!(start_index, first, "start index of cell {cell_key}");
+        }
     }
 }
@@ -1,6 +1,7 @@
 #define_import_path ignition::spatial_index::common
 
 alias Position = vec2<f32>;
+alias CellKey = u32;
 
 const U32_MAX: CellKey = 4294967295u;
 
@@ -15,43 +16,41 @@ struct NeighboursIter {
     cell_key_index: u32,
 }
 
-fn new_neighbours_iter(position: Vec2, length_scale: f32) -> NeighboursIter {
+fn new_neighbours_iter(position: Position, length_scale: f32, num_particles: u32) -> NeighboursIter {
     let offsets = neighbour_offsets(length_scale);
-    var cell_keys = [
-        get_cell_key(position + offsets[0]),
-        get_cell_key(position + offsets[1]),
-        get_cell_key(position + offsets[2]),
-        get_cell_key(position + offsets[3]),
-        get_cell_key(position + offsets[4]),
-        get_cell_key(position + offsets[5]),
-        get_cell_key(position + offsets[6]),
-        get_cell_key(position + offsets[7]),
-        get_cell_key(position + offsets[8]),
-    ];
+    var cell_keys = array<u32, 9>(
+        get_cell_key(position + offsets[0], length_scale, num_particles),
+        get_cell_key(position + offsets[1], length_scale, num_particles),
+        get_cell_key(position + offsets[2], length_scale, num_particles),
+        get_cell_key(position + offsets[3], length_scale, num_particles),
+        get_cell_key(position + offsets[4], length_scale, num_particles),
+        get_cell_key(position + offsets[5], length_scale, num_particles),
+        get_cell_key(position + offsets[6], length_scale, num_particles),
+        get_cell_key(position + offsets[7], length_scale, num_particles),
+        get_cell_key(position + offsets[8], length_scale, num_particles),
+    );
 
-    for (var i = 0u; i < 9; i += 1u) {
-      for (var j = i + 1u; j < 9; j += 1u) {
+    for (var i = 0u; i < 9u; i += 1u) {
+      for (var j = i + 1u; j < 9u; j += 1u) {
 	if cell_keys[i] > cell_keys[j] {
-	    var aux = cell_key[j];
+	    var aux = cell_keys[j];
 	    cell_keys[j] = cell_keys[i];
 	    cell_keys[i] = aux;
 	}
       }
     }
 
-    for (var i = 8u; i > 0; i -= 1u) {
-      if cell_keys[i] == cell_keys[i - 1] {
+    for (var i = 8u; i > 0u; i -= 1u) {
+      if cell_keys[i] == cell_keys[i - 1u] {
 	  cell_keys[i] = U32_MAX;
       }
     }
 
-    NeighboursIter {
-      
-    }
+    return NeighboursIter(cell_keys, 0u, 0u);
 }
 
 fn neighbour_offsets(size: f32) -> array<vec2<f32>, 9> {
-    return [
+    return array<vec2<f32>, 9>(
         vec2(-size, -size),
         vec2(-size, 0.0),
         vec2(-size, size),
@@ -61,7 +60,7 @@ fn neighbour_offsets(size: f32) -> array<vec2<f32>, 9> {
         vec2(size, -size),
         vec2(size, 0.0),
         vec2(size, size),
-    ];
+    );
 }
 
 fn get_potential_neighbours(iter: ptr<function, NeighboursIter>) {
@@ -69,7 +68,7 @@ fn get_potential_neighbours(iter: ptr<function, NeighboursIter>) {
   
 
 fn get_cell_key(position: Position, length_scale: f32, num_particles: u32) -> u32 {
-    return hash_position(position, params.length_scale) % num_particles;
+    return hash_position(position, length_scale) % num_particles;
 }
 
 fn hash_position(position: Position, length_scale: f32) -> u32 {
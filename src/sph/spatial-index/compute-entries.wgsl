@@ -1,9 +1,9 @@
-#import ignition::spatial_index::common::{hash_position, SpatialIndexEntry}
+#import ignition::spatial_index::common::{get_cell_key, SpatialIndexEntry}
 #import ignition::particle::Parameters
 
 @group(0) @binding(0) var<uniform>          params: Parameters;
 @group(0) @binding(1) var<storage, read> positions: array<vec2<f32>>;
-@group(0) @binding(2) var<storage, write>  entries: array<SpatialIndexEntry>;
+@group(0) @binding(2) var<storage, read_write> entries: array<SpatialIndexEntry>;
 
 @compute @workgroup_size(256)
 fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
@@ -1,7 +1,7 @@
 #import ignition::spatial_index::common::SpatialIndexEntry
 
 @group(0) @binding(0) var<storage, read>        entries: array<SpatialIndexEntry>;
-@group(0) @binding(1) var<storage, write> start_indices: array<u32>;
+@group(0) @binding(1) var<storage, read_write> start_indices: array<u32>;
 
 alias CellKey = u32;
 const U32_MAX: CellKey = 4294967295u;
@@ -17,10 +17,9 @@ fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     let previous_cell_key = get_previous_cell_key(entry_index);
     let current_cell_key = entries[entry_index].cell_key;
 
+    // Cells without entries keep `U32_MAX`
     if (previous_cell_key != current_cell_key) {
         start_indices[current_cell_key] = entry_index;
-    } else {
-        start_indices[current_cell_key] = U32_MAX; 
     }
 }
 
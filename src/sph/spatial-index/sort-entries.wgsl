@@ -1,4 +1,4 @@
-#import ignition::spatial_index::common::{hash_position, SpatialIndexEntry}
+#import ignition::spatial_index::common::SpatialIndexEntry
 
 @group(0) @binding(0) var<storage, read_write> entries: array<SpatialIndexEntry>;
 
@@ -8,9 +8,10 @@ fn main(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     for (var i = 0u; i < num_particles; i += 1u) {
       for (var j = i + 1u; j < num_particles; j += 1u) {
 	if entries[i].cell_key > entries[j].cell_key {
-	    var aux = entries[j].cell_key;
-	    entries[j].cell_key = entries[i].cell_key;
-	    entries[i].cell_key = aux;
+	    // Swap whole entries, so each cell key keeps its particle index
+	    let aux = entries[j];
+	    entries[j] = entries[i];
+	    entries[i] = aux;
 	  }
       }
     }